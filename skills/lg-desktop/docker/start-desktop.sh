@@ -32,6 +32,12 @@ if ! kill -0 "$I3_PID" 2>/dev/null; then
     exit 1
 fi
 
+# Optional accelerator: lg-inspect falls back to one-shot mode if it is not running
+if [ "${LG_INSPECT_DAEMON:-1}" = "1" ]; then
+    echo "[lg-desktop] Starting lg-inspect daemon"
+    lg-inspect --daemon &
+fi
+
 echo "[lg-desktop] Starting VNC server on port ${VNC_PORT}"
 if [ -n "${VNC_PASSWORD}" ]; then
     x11vnc -display "${DISPLAY}" -forever -shared -passwd "${VNC_PASSWORD}" -rfbport "${VNC_PORT}" -quiet &
@@ -0,0 +1,214 @@
+//! Access to the X desktop: window list, focus and window pixels.
+//!
+//! Design decision: `lg-inspect` talks to the X server in-process through
+//! [`x11::X11Backend`] by default. The original implementation spawned
+//! `xprop`, `xdotool`, `wmctrl` and `scrot` and parsed their text output,
+//! which cost ~20 processes per inspect and broke whenever a tool changed its
+//! output format. That path survives as [`shell::ShellBackend`] so the
+//! inspector still works when the X connection cannot be opened directly
+//! (e.g. a display reachable only through tools with special auth setup).
+
+pub mod shell;
+pub mod x11;
+
+use anyhow::Result;
+use image::RgbaImage;
+
+use crate::{Geometry, ListedWindow};
+
+/// Source of desktop state for one inspection.
+pub trait DesktopBackend {
+    /// Desktop size in pixels, `[0, 0]` if unknown.
+    fn desktop_size(&mut self) -> [u32; 2];
+
+    /// Id of the focused window (`0x`-prefixed hex), empty if none.
+    fn focused_window(&mut self) -> String;
+
+    /// Managed client windows in `_NET_CLIENT_LIST` order.
+    fn window_list(&mut self) -> Vec<ListedWindow>;
+
+    /// Mapped override-redirect windows (menus, dropdowns, tooltips,
+    /// completion popups), bottom to top. The window manager never sees
+    /// these, so they are missing from `window_list`.
+    fn popup_list(&mut self) -> Vec<ListedWindow>;
+
+    /// Ids of the windows currently on screen (viewable clients, then
+    /// popups), bottom to top.
+    fn stacking_order(&mut self) -> Vec<String>;
+
+    /// The whole screen as one image, for `--screen`.
+    fn capture_screen(&mut self) -> Result<RgbaImage, CaptureError>;
+
+    /// Read a window's pixels without changing focus or stacking.
+    fn capture_window(&mut self, window: &ListedWindow) -> Result<Capture, CaptureError>;
+}
+
+/// The visible pixels of a window.
+pub struct Capture {
+    pub image: RgbaImage,
+    /// Position of the image's top-left pixel inside the window. Non-zero
+    /// when the window hangs off the left or top screen edge and only its
+    /// on-screen part could be grabbed.
+    pub offset: [u32; 2],
+}
+
+impl Capture {
+    /// A capture of the on-screen rectangle `(left, top)` returned by
+    /// [`clip_to_screen`] for a window at `geometry`.
+    pub fn clipped(image: RgbaImage, geometry: &Geometry, left: u32, top: u32) -> Self {
+        let inset = |screen: u32, window: i32| {
+            u32::try_from(i64::from(screen) - i64::from(window)).unwrap_or(0)
+        };
+        Self {
+            image,
+            offset: [inset(left, geometry.x), inset(top, geometry.y)],
+        }
+    }
+}
+
+/// Why a window has no capture. Each variant carries a human-readable
+/// reason that is passed through to the output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureError {
+    /// Iconified: the X server keeps no pixels for it.
+    Minimized(String),
+    /// Not on the visible screen: another workspace, or moved out of view.
+    Offscreen(String),
+    /// The grab itself failed.
+    Failed(String),
+}
+
+/// Which backend the user asked for via `--backend` / `LG_INSPECT_BACKEND`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum BackendChoice {
+    /// Native X11, falling back to shell tools if the connection fails.
+    #[default]
+    Auto,
+    X11,
+    Shell,
+}
+
+impl BackendChoice {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "auto" => Some(Self::Auto),
+            "x11" => Some(Self::X11),
+            "shell" => Some(Self::Shell),
+            _ => None,
+        }
+    }
+}
+
+/// Open the requested backend. Only an explicit `x11` choice turns a failed
+/// connection into an error; `auto` degrades to the shell tools.
+pub fn connect(choice: BackendChoice) -> Result<Box<dyn DesktopBackend>> {
+    match choice {
+        BackendChoice::Shell => Ok(Box::new(shell::ShellBackend::new())),
+        BackendChoice::X11 => Ok(Box::new(x11::X11Backend::connect()?)),
+        BackendChoice::Auto => match x11::X11Backend::connect() {
+            Ok(backend) => Ok(Box::new(backend)),
+            Err(e) => {
+                eprintln!(
+                    "[lg-inspect] warning: native X11 connection failed, using shell tools: {e:#}"
+                );
+                Ok(Box::new(shell::ShellBackend::new()))
+            }
+        },
+    }
+}
+
+/// Format an X window id the way `wmctrl -l` does (`0x` + 8 hex digits).
+pub fn format_window_id(window: u32) -> String {
+    format!("0x{window:08x}")
+}
+
+/// Short form of an EWMH atom name: `_NET_WM_STATE_DEMANDS_ATTENTION` with
+/// prefix `_NET_WM_STATE_` becomes `demands_attention`.
+pub fn short_atom_name(name: &str, prefix: &str) -> String {
+    name.strip_prefix(prefix).unwrap_or(name).to_lowercase()
+}
+
+/// Clip a window's geometry to the screen, returning `(x, y, w, h)` in
+/// screen pixels, or `None` if nothing of the window is on screen.
+pub fn clip_to_screen(
+    geometry: &Geometry,
+    screen_w: u32,
+    screen_h: u32,
+) -> Option<(u32, u32, u32, u32)> {
+    let left = i64::from(geometry.x).max(0);
+    let top = i64::from(geometry.y).max(0);
+    let right = (i64::from(geometry.x) + i64::from(geometry.w)).min(i64::from(screen_w));
+    let bottom = (i64::from(geometry.y) + i64::from(geometry.h)).min(i64::from(screen_h));
+    if right <= left || bottom <= top {
+        return None;
+    }
+    Some((
+        left as u32,
+        top as u32,
+        (right - left) as u32,
+        (bottom - top) as u32,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_window_id() {
+        assert_eq!(format_window_id(0x0340_0003), "0x03400003");
+        assert_eq!(format_window_id(0x1c), "0x0000001c");
+    }
+
+    #[test]
+    fn test_short_atom_name() {
+        assert_eq!(
+            short_atom_name("_NET_WM_WINDOW_TYPE_DIALOG", "_NET_WM_WINDOW_TYPE_"),
+            "dialog"
+        );
+        assert_eq!(
+            short_atom_name("_KDE_NET_WM_STATE_X", "_NET_WM_STATE_"),
+            "_kde_net_wm_state_x"
+        );
+    }
+
+    #[test]
+    fn test_clip_to_screen_inside() {
+        let g = Geometry {
+            x: 10,
+            y: 20,
+            w: 100,
+            h: 200,
+        };
+        assert_eq!(clip_to_screen(&g, 1280, 1024), Some((10, 20, 100, 200)));
+    }
+
+    #[test]
+    fn test_clip_to_screen_partially_offscreen() {
+        let g = Geometry {
+            x: -50,
+            y: 1000,
+            w: 100,
+            h: 100,
+        };
+        assert_eq!(clip_to_screen(&g, 1280, 1024), Some((0, 1000, 50, 24)));
+    }
+
+    #[test]
+    fn test_clip_to_screen_fully_offscreen() {
+        let g = Geometry {
+            x: 1280,
+            y: 0,
+            w: 100,
+            h: 100,
+        };
+        assert_eq!(clip_to_screen(&g, 1280, 1024), None);
+    }
+
+    #[test]
+    fn test_backend_choice_parse() {
+        assert_eq!(BackendChoice::parse("x11"), Some(BackendChoice::X11));
+        assert_eq!(BackendChoice::parse("shell"), Some(BackendChoice::Shell));
+        assert_eq!(BackendChoice::parse("wayland"), None);
+    }
+}
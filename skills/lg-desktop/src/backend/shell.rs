@@ -0,0 +1,482 @@
+//! Fallback backend built on `xprop`, `xdotool`, `wmctrl` and `scrot`.
+
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fs;
+use std::process::Command;
+
+use image::RgbaImage;
+
+use super::{
+    clip_to_screen, format_window_id, short_atom_name, Capture, CaptureError, DesktopBackend,
+};
+use crate::{Geometry, ListedWindow, WindowMeta};
+
+#[derive(Default)]
+pub struct ShellBackend {
+    /// Root window capture shared by all windows of one inspection,
+    /// taken on the first `capture_window` call.
+    screen: OnceCell<Result<RgbaImage, String>>,
+    current_desktop: OnceCell<Option<i64>>,
+    /// Workspace of each window from the last `window_list`.
+    desktops: HashMap<String, i64>,
+}
+
+impl ShellBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl DesktopBackend for ShellBackend {
+    fn desktop_size(&mut self) -> [u32; 2] {
+        get_desktop_size()
+    }
+
+    fn focused_window(&mut self) -> String {
+        get_focused_window()
+    }
+
+    fn window_list(&mut self) -> Vec<ListedWindow> {
+        let windows = get_window_list();
+        self.desktops = windows.iter().map(|w| (w.id.clone(), w.desktop)).collect();
+        windows
+    }
+
+    /// None of the shell tools report the override-redirect flag, so popups
+    /// are only visible through the X11 backend.
+    fn popup_list(&mut self) -> Vec<ListedWindow> {
+        Vec::new()
+    }
+
+    /// Minimized windows cannot be told apart here (that would take an
+    /// `xprop` per window), so they crop to whatever covers their rectangle.
+    fn stacking_order(&mut self) -> Vec<String> {
+        let current_desktop = *self.current_desktop.get_or_init(get_current_desktop);
+        get_stacking_order()
+            .into_iter()
+            .filter(|id| match (self.desktops.get(id), current_desktop) {
+                (Some(&desktop), Some(current)) => desktop < 0 || desktop == current,
+                _ => true,
+            })
+            .collect()
+    }
+
+    fn capture_screen(&mut self) -> Result<RgbaImage, CaptureError> {
+        self.screen
+            .get_or_init(capture_screen)
+            .clone()
+            .map_err(CaptureError::Failed)
+    }
+
+    fn capture_window(&mut self, window: &ListedWindow) -> Result<Capture, CaptureError> {
+        // Windows on another workspace are not mapped, so the root capture
+        // would show whatever covers their rectangle instead.
+        let current_desktop = *self.current_desktop.get_or_init(get_current_desktop);
+        if window.desktop >= 0 && current_desktop.is_some_and(|d| d != window.desktop) {
+            return Err(CaptureError::Offscreen(format!(
+                "on workspace {}",
+                window.desktop
+            )));
+        }
+        let screen = self
+            .screen
+            .get_or_init(capture_screen)
+            .as_ref()
+            .map_err(|e| CaptureError::Failed(e.clone()))?;
+        let (left, top, width, height) =
+            clip_to_screen(&window.geometry, screen.width(), screen.height())
+                .ok_or_else(|| CaptureError::Offscreen("outside the screen".to_string()))?;
+        let image = image::imageops::crop_imm(screen, left, top, width, height).to_image();
+        Ok(Capture::clipped(image, &window.geometry, left, top))
+    }
+}
+
+fn get_desktop_size() -> [u32; 2] {
+    let output = Command::new("xprop")
+        .args(["-root", "_NET_DESKTOP_GEOMETRY"])
+        .output();
+
+    match output {
+        Ok(out) if out.status.success() => {
+            let text = String::from_utf8_lossy(&out.stdout);
+            // Format: "_NET_DESKTOP_GEOMETRY(CARDINAL) = 1920, 1080"
+            if let Some(eq_pos) = text.find('=') {
+                let values: Vec<u32> = text[eq_pos + 1..]
+                    .split(',')
+                    .filter_map(|s| s.trim().parse().ok())
+                    .collect();
+                if values.len() == 2 {
+                    return [values[0], values[1]];
+                }
+            }
+            eprintln!("[lg-inspect] warning: could not parse desktop size from xprop output");
+            [0, 0]
+        }
+        Ok(out) => {
+            eprintln!(
+                "[lg-inspect] warning: xprop failed (exit {}): {}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+            [0, 0]
+        }
+        Err(e) => {
+            eprintln!("[lg-inspect] warning: failed to run xprop: {e}");
+            [0, 0]
+        }
+    }
+}
+
+fn get_focused_window() -> String {
+    let output = Command::new("xdotool").arg("getactivewindow").output();
+
+    match output {
+        // xdotool prints decimal ids; normalize to the wmctrl hex form so
+        // `focused_window` can be matched against `windows[].id`.
+        Ok(out) if out.status.success() => {
+            let text = String::from_utf8_lossy(&out.stdout);
+            match text.trim().parse::<u32>() {
+                Ok(id) => format_window_id(id),
+                Err(_) => text.trim().to_string(),
+            }
+        }
+        Ok(out) => {
+            eprintln!(
+                "[lg-inspect] warning: xdotool getactivewindow failed (exit {})",
+                out.status
+            );
+            String::new()
+        }
+        Err(e) => {
+            eprintln!("[lg-inspect] warning: failed to run xdotool: {e}");
+            String::new()
+        }
+    }
+}
+
+fn get_window_list() -> Vec<ListedWindow> {
+    let output = Command::new("wmctrl").args(["-lG"]).output();
+
+    let out = match output {
+        Ok(out) if out.status.success() => out,
+        Ok(out) => {
+            eprintln!(
+                "[lg-inspect] warning: wmctrl failed (exit {}): {}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+            return Vec::new();
+        }
+        Err(e) => {
+            eprintln!("[lg-inspect] warning: failed to run wmctrl: {e}");
+            return Vec::new();
+        }
+    };
+
+    let mut windows = parse_wmctrl_list(&String::from_utf8_lossy(&out.stdout));
+    correct_origins(&mut windows, &get_client_origins());
+    for window in &mut windows {
+        window.meta = get_window_meta(&window.id);
+    }
+    windows
+}
+
+/// Properties `get_window_meta` asks `xprop` for.
+const META_PROPERTIES: [&str; 5] = [
+    "WM_CLASS",
+    "_NET_WM_PID",
+    "_NET_WM_WINDOW_TYPE",
+    "_NET_WM_STATE",
+    "WM_TRANSIENT_FOR",
+];
+
+/// Class, pid, type, state and owner of one window, from one `xprop` run.
+fn get_window_meta(id: &str) -> WindowMeta {
+    match Command::new("xprop")
+        .args(["-id", id])
+        .args(META_PROPERTIES)
+        .output()
+    {
+        Ok(out) if out.status.success() => parse_xprop_meta(&String::from_utf8_lossy(&out.stdout)),
+        // The window may have closed since wmctrl listed it.
+        Ok(_) => WindowMeta::default(),
+        Err(e) => {
+            eprintln!("[lg-inspect] warning: failed to run xprop: {e}");
+            WindowMeta::default()
+        }
+    }
+}
+
+/// Parse `xprop` lines such as `WM_CLASS(STRING) = "xterm", "XTerm"` or
+/// `_NET_WM_STATE(ATOM) = _NET_WM_STATE_MODAL`. Properties the window does
+/// not set print `not found.` and are skipped.
+fn parse_xprop_meta(text: &str) -> WindowMeta {
+    let mut meta = WindowMeta::default();
+    for line in text.lines().filter(|l| !l.ends_with("not found.")) {
+        let Some((name, value)) = line.split_once(" = ").or_else(|| line.split_once(": ")) else {
+            continue;
+        };
+        let name = name.split('(').next().unwrap_or_default();
+        let list = || value.split(',').map(str::trim).filter(|v| !v.is_empty());
+        match name {
+            "WM_CLASS" => {
+                let mut parts = list().map(|v| v.trim_matches('"').to_string());
+                meta.instance = parts.next().unwrap_or_default();
+                meta.class = parts.next().unwrap_or_default();
+            }
+            "_NET_WM_PID" => meta.pid = value.trim().parse().ok(),
+            "_NET_WM_WINDOW_TYPE" => {
+                meta.window_type = list()
+                    .next()
+                    .map(|atom| short_atom_name(atom, "_NET_WM_WINDOW_TYPE_"));
+            }
+            "_NET_WM_STATE" => {
+                meta.state = list()
+                    .map(|atom| short_atom_name(atom, "_NET_WM_STATE_"))
+                    .collect();
+            }
+            "WM_TRANSIENT_FOR" => {
+                meta.transient_for = parse_xprop_windows(line).into_iter().next();
+            }
+            _ => {}
+        }
+    }
+    meta
+}
+
+/// Absolute content-area origin of every window, from one `xwininfo -root
+/// -tree` run.
+///
+/// Design decision: `wmctrl -lG` adds a client's offset inside its frame to
+/// its already-absolute position, so under a reparenting window manager every
+/// window is reported shifted right and down by its decorations (~20-30px of
+/// title bar). Cropping the root capture at that rectangle cut off the top of
+/// the window and made every element's y wrong by the title bar height.
+/// `_NET_FRAME_EXTENTS` would tell us the shift, but not every WM sets it
+/// (i3 does not) and reading it costs an `xprop` per window, while
+/// `xwininfo` reports the true absolute origin of the whole tree at once.
+fn get_client_origins() -> HashMap<String, (i32, i32)> {
+    match Command::new("xwininfo").args(["-root", "-tree"]).output() {
+        Ok(out) if out.status.success() => {
+            parse_xwininfo_tree(&String::from_utf8_lossy(&out.stdout))
+        }
+        Ok(out) => {
+            eprintln!(
+                "[lg-inspect] warning: xwininfo failed (exit {}); window positions may include decorations",
+                out.status
+            );
+            HashMap::new()
+        }
+        Err(e) => {
+            eprintln!("[lg-inspect] warning: failed to run xwininfo: {e}");
+            HashMap::new()
+        }
+    }
+}
+
+/// Parse `xwininfo -tree` lines such as
+/// `0x1a00003 "Terminal": ("xterm" "XTerm")  484x316+0+0  +1+20`
+/// into window id -> absolute origin (the last field).
+fn parse_xwininfo_tree(text: &str) -> HashMap<String, (i32, i32)> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let id = fields.next()?.strip_prefix("0x")?;
+            let id = u32::from_str_radix(id, 16).ok()?;
+            let absolute = line.split_whitespace().next_back()?.strip_prefix('+')?;
+            // "+-5+20" for a window hanging off the left edge.
+            let (x, y) = absolute.rsplit_once('+')?;
+            Some((format_window_id(id), (x.parse().ok()?, y.parse().ok()?)))
+        })
+        .collect()
+}
+
+/// Replace wmctrl's positions with the true client origins where known.
+fn correct_origins(windows: &mut [ListedWindow], origins: &HashMap<String, (i32, i32)>) {
+    for window in windows {
+        if let Some(&(x, y)) = origins.get(&window.id) {
+            window.geometry.x = x;
+            window.geometry.y = y;
+        }
+    }
+}
+
+fn parse_wmctrl_list(text: &str) -> Vec<ListedWindow> {
+    let mut windows = Vec::new();
+
+    for line in text.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        // wmctrl -lG format: ID DESKTOP X Y W H HOSTNAME TITLE...
+        if parts.len() >= 8 {
+            let id = parts[0].to_string();
+            let desktop = parts[1].parse().unwrap_or(-1);
+            let x = parts[2].parse().unwrap_or(0);
+            let y = parts[3].parse().unwrap_or(0);
+            let w = parts[4].parse().unwrap_or(0);
+            let h = parts[5].parse().unwrap_or(0);
+            let title = parts[7..].join(" ");
+            let geometry = Geometry { x, y, w, h };
+            windows.push(ListedWindow {
+                id,
+                geometry,
+                title,
+                desktop,
+                meta: WindowMeta::default(),
+            });
+        }
+    }
+
+    windows
+}
+
+/// Client ids from `_NET_CLIENT_LIST_STACKING`, bottom to top.
+fn get_stacking_order() -> Vec<String> {
+    match Command::new("xprop")
+        .args(["-root", "_NET_CLIENT_LIST_STACKING"])
+        .output()
+    {
+        Ok(out) if out.status.success() => {
+            parse_xprop_windows(&String::from_utf8_lossy(&out.stdout))
+        }
+        Ok(out) => {
+            eprintln!(
+                "[lg-inspect] warning: xprop failed (exit {}): {}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+            Vec::new()
+        }
+        Err(e) => {
+            eprintln!("[lg-inspect] warning: failed to run xprop: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Parse `NAME(WINDOW): window id # 0x1a00003, 0x1c00001` into wmctrl-style ids.
+fn parse_xprop_windows(text: &str) -> Vec<String> {
+    let Some((_, list)) = text.split_once('#') else {
+        return Vec::new();
+    };
+    list.split(',')
+        .filter_map(|id| u32::from_str_radix(id.trim().strip_prefix("0x")?, 16).ok())
+        .map(format_window_id)
+        .collect()
+}
+
+/// Index of the currently displayed desktop (the `*` row of `wmctrl -d`).
+fn get_current_desktop() -> Option<i64> {
+    let out = Command::new("wmctrl").arg("-d").output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .find(|line| line.split_whitespace().nth(1) == Some("*"))
+        .and_then(|line| line.split_whitespace().next()?.parse().ok())
+}
+
+/// Capture the whole root window once.
+///
+/// Design decision: we used to `xdotool windowfocus` each window and run
+/// `scrot -u`, which fired focus events at the application (autosave,
+/// validation, closing menus) and raced with input the agent was typing.
+/// Grabbing the root window and cropping each window out of it reads pixels
+/// straight from the X server without touching focus or stacking, and costs
+/// one process per inspect instead of two per window.
+fn capture_screen() -> Result<RgbaImage, String> {
+    let failed = |message: String| {
+        eprintln!("[lg-inspect] warning: {message}");
+        message
+    };
+    // Private to this process, so concurrent inspects cannot read each
+    // other's capture, and removed once decoded.
+    let path = std::env::temp_dir().join(format!("lg-inspect-root-{}.png", std::process::id()));
+    let path = path.to_string_lossy();
+
+    let status = Command::new("scrot").args(["-z", "-o", &path]).status();
+
+    let screen = match status {
+        Ok(s) if s.success() => match image::open(&*path) {
+            Ok(img) => Ok(img.to_rgba8()),
+            Err(e) => Err(failed(format!("failed to decode root capture {path}: {e}"))),
+        },
+        Ok(s) => Err(failed(format!(
+            "scrot exited with {} for root window",
+            s.code().map_or("signal".to_string(), |c| c.to_string())
+        ))),
+        Err(e) => Err(failed(format!("failed to execute scrot: {e}"))),
+    };
+    let _ = fs::remove_file(&*path);
+    screen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_wmctrl_list() {
+        let text = "0x03a00003  0 0    0    640  512  host Terminal - bash\n\
+                    0x03c00001 -1 640  0    640  1024 host Sticky\n\
+                    garbage line\n";
+        let windows = parse_wmctrl_list(text);
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows[0].id, "0x03a00003");
+        assert_eq!(windows[0].title, "Terminal - bash");
+        assert_eq!(windows[0].geometry.w, 640);
+        assert_eq!(windows[1].desktop, -1);
+        assert_eq!(windows[1].geometry.x, 640);
+    }
+
+    #[test]
+    fn test_parse_xprop_meta() {
+        let text = "WM_CLASS(STRING) = \"gedit\", \"Gedit\"\n\
+                    _NET_WM_PID(CARDINAL) = 4242\n\
+                    _NET_WM_WINDOW_TYPE(ATOM) = _NET_WM_WINDOW_TYPE_DIALOG, _NET_WM_WINDOW_TYPE_NORMAL\n\
+                    _NET_WM_STATE(ATOM) = _NET_WM_STATE_MODAL, _NET_WM_STATE_DEMANDS_ATTENTION\n\
+                    WM_TRANSIENT_FOR(WINDOW): window id # 0x1a00003\n";
+        let meta = parse_xprop_meta(text);
+        assert_eq!(
+            (meta.instance.as_str(), meta.class.as_str()),
+            ("gedit", "Gedit")
+        );
+        assert_eq!(meta.pid, Some(4242));
+        assert_eq!(meta.window_type.as_deref(), Some("dialog"));
+        assert_eq!(meta.state, ["modal", "demands_attention"]);
+        assert_eq!(meta.transient_for.as_deref(), Some("0x01a00003"));
+
+        let bare = parse_xprop_meta("WM_CLASS:  not found.\n_NET_WM_STATE(ATOM) = \n");
+        assert_eq!(bare, WindowMeta::default());
+    }
+
+    #[test]
+    fn test_parse_xprop_windows() {
+        let text = "_NET_CLIENT_LIST_STACKING(WINDOW): window id # 0x1a00003, 0x1c00001\n";
+        assert_eq!(parse_xprop_windows(text), ["0x01a00003", "0x01c00001"]);
+        assert!(parse_xprop_windows("_NET_CLIENT_LIST_STACKING:  not found.\n").is_empty());
+    }
+
+    #[test]
+    fn test_xwininfo_origins_correct_wmctrl_geometry() {
+        // An i3 window whose content starts below a 20px title bar at
+        // (1, 20): wmctrl reports it shifted by the frame offset again.
+        let wmctrl = "0x01a00003  0 2    40   484  316  host Terminal\n\
+                      0x01c00001  0 -3   -3   200  100  host Offscreen\n";
+        let xwininfo = "\n\
+            xwininfo: Window id: 0x2a1 (the root window) (has no name)\n\
+              Root window id: 0x2a1 (the root window) (has no name)\n\
+              2 children:\n\
+              0x400003 (has no name): ()  486x337+0+0  +0+0\n\
+                 1 child:\n\
+                 0x1a00003 \"Terminal\": (\"xterm\" \"XTerm\")  484x316+1+20  +1+20\n\
+              0x1c00001 \"Offscreen\": ()  200x100+-5+-5  +-5+-5\n";
+        let mut windows = parse_wmctrl_list(wmctrl);
+        correct_origins(&mut windows, &parse_xwininfo_tree(xwininfo));
+        let g = windows[0].geometry;
+        assert_eq!((g.x, g.y, g.w, g.h), (1, 20, 484, 316));
+        let g = windows[1].geometry;
+        assert_eq!((g.x, g.y), (-5, -5));
+    }
+}
@@ -0,0 +1,566 @@
+//! Native X11 backend speaking the X protocol in-process via `x11rb`.
+//!
+//! Reads the EWMH properties the window manager maintains on the root window
+//! (`_NET_DESKTOP_GEOMETRY`, `_NET_ACTIVE_WINDOW`, `_NET_CLIENT_LIST`) and
+//! on each client (`WM_CLASS`, `_NET_WM_PID`, `_NET_WM_WINDOW_TYPE`, ...), and
+//! grabs window contents with `GetImage`, so one inspect is a handful of
+//! round trips on a single connection instead of a process per query.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use image::{Rgba, RgbaImage};
+use x11rb::connection::Connection;
+use x11rb::image::{Image, PixelLayout};
+use x11rb::protocol::damage::{ConnectionExt as _, Damage, ReportLevel};
+use x11rb::protocol::xfixes::ConnectionExt as _;
+use x11rb::protocol::xproto::{
+    AtomEnum, ChangeWindowAttributesAux, ConnectionExt, EventMask, MapState, Visualid, Visualtype,
+    Window, WindowClass,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use x11rb::NONE;
+
+use super::{
+    clip_to_screen, format_window_id, short_atom_name, Capture, CaptureError, DesktopBackend,
+};
+use crate::{Geometry, ListedWindow, WindowMeta};
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        _NET_DESKTOP_GEOMETRY,
+        _NET_ACTIVE_WINDOW,
+        _NET_CLIENT_LIST,
+        _NET_CLIENT_LIST_STACKING,
+        _NET_WM_NAME,
+        _NET_WM_DESKTOP,
+        _NET_WM_PID,
+        _NET_WM_WINDOW_TYPE,
+        _NET_WM_STATE,
+        _NET_WM_STATE_HIDDEN,
+        UTF8_STRING,
+    }
+}
+
+/// Longest property value we read, in 32-bit units.
+const MAX_PROPERTY_LEN: u32 = 4096;
+
+pub struct X11Backend {
+    conn: RustConnection,
+    root: Window,
+    atoms: Atoms,
+    /// Names of atoms seen in window types and states, fetched once each.
+    atom_names: RefCell<HashMap<u32, String>>,
+}
+
+impl X11Backend {
+    /// Connect to the display named by `$DISPLAY`.
+    pub fn connect() -> Result<Self> {
+        let (conn, screen_num) =
+            x11rb::connect(None).context("failed to connect to the X server")?;
+        let root = conn.setup().roots[screen_num].root;
+        let atoms = Atoms::new(&conn)?
+            .reply()
+            .context("failed to intern EWMH atoms")?;
+        Ok(Self {
+            conn,
+            root,
+            atoms,
+            atom_names: RefCell::default(),
+        })
+    }
+
+    /// Read a 32-bit property (CARDINAL, WINDOW, ...) as a list of values.
+    fn property_u32(&self, window: Window, property: u32, type_: AtomEnum) -> Option<Vec<u32>> {
+        let reply = self
+            .conn
+            .get_property(false, window, property, type_, 0, MAX_PROPERTY_LEN)
+            .ok()?
+            .reply()
+            .ok()?;
+        let values = reply.value32()?.collect();
+        Some(values)
+    }
+
+    /// Window title from `_NET_WM_NAME` (UTF-8), falling back to `WM_NAME`.
+    fn window_title(&self, window: Window) -> String {
+        let utf8 = self
+            .conn
+            .get_property(
+                false,
+                window,
+                self.atoms._NET_WM_NAME,
+                self.atoms.UTF8_STRING,
+                0,
+                MAX_PROPERTY_LEN,
+            )
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .filter(|reply| !reply.value.is_empty());
+        let reply = utf8.or_else(|| {
+            self.conn
+                .get_property(
+                    false,
+                    window,
+                    AtomEnum::WM_NAME,
+                    AtomEnum::ANY,
+                    0,
+                    MAX_PROPERTY_LEN,
+                )
+                .ok()?
+                .reply()
+                .ok()
+        });
+        reply
+            .map(|r| String::from_utf8_lossy(&r.value).into_owned())
+            .unwrap_or_default()
+    }
+
+    fn atom_name(&self, atom: u32) -> Option<String> {
+        if let Some(name) = self.atom_names.borrow().get(&atom) {
+            return Some(name.clone());
+        }
+        let reply = self.conn.get_atom_name(atom).ok()?.reply().ok()?;
+        let name = String::from_utf8_lossy(&reply.name).into_owned();
+        self.atom_names.borrow_mut().insert(atom, name.clone());
+        Some(name)
+    }
+
+    /// Class, pid, type, state and owner from the window's properties.
+    fn window_meta(&self, window: Window) -> WindowMeta {
+        let class = self
+            .conn
+            .get_property(
+                false,
+                window,
+                AtomEnum::WM_CLASS,
+                AtomEnum::STRING,
+                0,
+                MAX_PROPERTY_LEN,
+            )
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .map(|reply| reply.value)
+            .unwrap_or_default();
+        // Two NUL-terminated strings: instance, then class.
+        let mut parts = class
+            .split(|&b| b == 0)
+            .map(|part| String::from_utf8_lossy(part).into_owned());
+        let instance = parts.next().unwrap_or_default();
+        let class = parts.next().unwrap_or_default();
+
+        let first = |property, type_| {
+            self.property_u32(window, property, type_)
+                .and_then(|values| values.first().copied())
+        };
+        let atom_names = |property, prefix| {
+            self.property_u32(window, property, AtomEnum::ATOM)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|atom| self.atom_name(atom))
+                .map(|name| short_atom_name(&name, prefix))
+                .collect::<Vec<_>>()
+        };
+        WindowMeta {
+            class,
+            instance,
+            pid: first(self.atoms._NET_WM_PID, AtomEnum::CARDINAL),
+            window_type: atom_names(self.atoms._NET_WM_WINDOW_TYPE, "_NET_WM_WINDOW_TYPE_")
+                .into_iter()
+                .next(),
+            state: atom_names(self.atoms._NET_WM_STATE, "_NET_WM_STATE_"),
+            transient_for: first(AtomEnum::WM_TRANSIENT_FOR.into(), AtomEnum::WINDOW)
+                .filter(|&w| w != 0)
+                .map(format_window_id),
+        }
+    }
+
+    /// Absolute position and size of a window's content area.
+    fn window_geometry(&self, window: Window) -> Option<Geometry> {
+        let geom = self.conn.get_geometry(window).ok()?.reply().ok()?;
+        let origin = self
+            .conn
+            .translate_coordinates(window, self.root, 0, 0)
+            .ok()?
+            .reply()
+            .ok()?;
+        Some(Geometry {
+            x: i32::from(origin.dst_x),
+            y: i32::from(origin.dst_y),
+            w: u32::from(geom.width),
+            h: u32::from(geom.height),
+        })
+    }
+
+    fn find_visual(&self, visual: Visualid) -> Option<Visualtype> {
+        self.conn
+            .setup()
+            .roots
+            .iter()
+            .flat_map(|screen| &screen.allowed_depths)
+            .flat_map(|depth| &depth.visuals)
+            .find(|v| v.visual_id == visual)
+            .copied()
+    }
+
+    /// Why a managed window is not viewable: iconified (`_NET_WM_STATE_HIDDEN`)
+    /// or on a workspace other than the current one.
+    fn unmapped_reason(&self, id: Window, window: &ListedWindow) -> CaptureError {
+        let hidden = self
+            .property_u32(id, self.atoms._NET_WM_STATE, AtomEnum::ATOM)
+            .is_some_and(|state| state.contains(&self.atoms._NET_WM_STATE_HIDDEN));
+        if hidden {
+            CaptureError::Minimized("window is minimized".to_string())
+        } else if window.desktop >= 0 {
+            CaptureError::Offscreen(format!("on workspace {}", window.desktop))
+        } else {
+            CaptureError::Offscreen("window is not mapped".to_string())
+        }
+    }
+}
+
+impl DesktopBackend for X11Backend {
+    fn desktop_size(&mut self) -> [u32; 2] {
+        match self.property_u32(
+            self.root,
+            self.atoms._NET_DESKTOP_GEOMETRY,
+            AtomEnum::CARDINAL,
+        ) {
+            Some(values) if values.len() == 2 => [values[0], values[1]],
+            _ => {
+                eprintln!("[lg-inspect] warning: could not read _NET_DESKTOP_GEOMETRY");
+                [0, 0]
+            }
+        }
+    }
+
+    fn focused_window(&mut self) -> String {
+        self.property_u32(self.root, self.atoms._NET_ACTIVE_WINDOW, AtomEnum::WINDOW)
+            .and_then(|values| values.first().copied())
+            .filter(|&w| w != 0)
+            .map(format_window_id)
+            .unwrap_or_default()
+    }
+
+    fn window_list(&mut self) -> Vec<ListedWindow> {
+        let Some(clients) =
+            self.property_u32(self.root, self.atoms._NET_CLIENT_LIST, AtomEnum::WINDOW)
+        else {
+            eprintln!("[lg-inspect] warning: could not read _NET_CLIENT_LIST");
+            return Vec::new();
+        };
+
+        clients
+            .into_iter()
+            .filter_map(|window| {
+                // Windows can vanish between reading the list and querying them.
+                let geometry = self.window_geometry(window)?;
+                let desktop = self
+                    .property_u32(window, self.atoms._NET_WM_DESKTOP, AtomEnum::CARDINAL)
+                    .and_then(|v| v.first().copied())
+                    .map_or(-1, |d| if d == u32::MAX { -1 } else { i64::from(d) });
+                Some(ListedWindow {
+                    id: format_window_id(window),
+                    geometry,
+                    title: self.window_title(window),
+                    desktop,
+                    meta: self.window_meta(window),
+                })
+            })
+            .collect()
+    }
+
+    fn popup_list(&mut self) -> Vec<ListedWindow> {
+        let Some(tree) = self
+            .conn
+            .query_tree(self.root)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+        else {
+            eprintln!("[lg-inspect] warning: could not query the root window's children");
+            return Vec::new();
+        };
+
+        tree.children
+            .into_iter()
+            .filter(|&window| {
+                self.conn
+                    .get_window_attributes(window)
+                    .ok()
+                    .and_then(|cookie| cookie.reply().ok())
+                    .is_some_and(|attrs| {
+                        attrs.override_redirect
+                            && attrs.map_state == MapState::VIEWABLE
+                            && attrs.class == WindowClass::INPUT_OUTPUT
+                    })
+            })
+            .filter_map(|window| {
+                let geometry = self.window_geometry(window)?;
+                // Toolkits map 1x1 helper windows that hold no pixels.
+                if geometry.w <= 1 || geometry.h <= 1 {
+                    return None;
+                }
+                Some(ListedWindow {
+                    id: format_window_id(window),
+                    geometry,
+                    title: self.window_title(window),
+                    desktop: -1,
+                    meta: self.window_meta(window),
+                })
+            })
+            .collect()
+    }
+
+    fn stacking_order(&mut self) -> Vec<String> {
+        let clients = self
+            .property_u32(
+                self.root,
+                self.atoms._NET_CLIENT_LIST_STACKING,
+                AtomEnum::WINDOW,
+            )
+            .unwrap_or_else(|| {
+                eprintln!("[lg-inspect] warning: could not read _NET_CLIENT_LIST_STACKING");
+                Vec::new()
+            });
+        let mut order: Vec<String> = clients
+            .into_iter()
+            .filter(|&window| {
+                self.conn
+                    .get_window_attributes(window)
+                    .ok()
+                    .and_then(|cookie| cookie.reply().ok())
+                    .is_some_and(|attrs| attrs.map_state == MapState::VIEWABLE)
+            })
+            .map(format_window_id)
+            .collect();
+        // Override-redirect windows always sit above managed clients.
+        order.extend(self.popup_list().into_iter().map(|popup| popup.id));
+        order
+    }
+
+    fn capture_screen(&mut self) -> Result<RgbaImage, CaptureError> {
+        let failed = |e: &dyn std::fmt::Display| {
+            CaptureError::Failed(format!("GetImage failed for the root window: {e}"))
+        };
+        let root = self
+            .conn
+            .get_geometry(self.root)
+            .map_err(|e| failed(&e))?
+            .reply()
+            .map_err(|e| failed(&e))?;
+        let (image, visual) = Image::get(&self.conn, self.root, 0, 0, root.width, root.height)
+            .map_err(|e| failed(&e))?;
+        let layout = self
+            .find_visual(visual)
+            .and_then(|v| PixelLayout::from_visual_type(v).ok())
+            .ok_or_else(|| CaptureError::Failed(format!("unsupported visual {visual:#x}")))?;
+        Ok(to_rgba(&image, layout))
+    }
+
+    fn capture_window(&mut self, window: &ListedWindow) -> Result<Capture, CaptureError> {
+        let gone =
+            |e: &dyn std::fmt::Display| CaptureError::Failed(format!("window vanished: {e}"));
+        let id = parse_window_id(&window.id)
+            .ok_or_else(|| CaptureError::Failed(format!("bad window id {}", window.id)))?;
+        // GetImage on an unmapped window is a Match error; check first so
+        // minimized windows and hidden workspaces get a clear status.
+        let attrs = self
+            .conn
+            .get_window_attributes(id)
+            .map_err(|e| gone(&e))?
+            .reply()
+            .map_err(|e| gone(&e))?;
+        if attrs.map_state != MapState::VIEWABLE {
+            return Err(self.unmapped_reason(id, window));
+        }
+        // GetImage also fails for rectangles outside the screen, so only ask
+        // for the on-screen part of the window.
+        let root = self
+            .conn
+            .get_geometry(self.root)
+            .map_err(|e| gone(&e))?
+            .reply()
+            .map_err(|e| gone(&e))?;
+        let (left, top, width, height) = clip_to_screen(
+            &window.geometry,
+            u32::from(root.width),
+            u32::from(root.height),
+        )
+        .ok_or_else(|| CaptureError::Offscreen("outside the screen".to_string()))?;
+        let (image, visual) = Image::get(
+            &self.conn,
+            id,
+            (i64::from(left) - i64::from(window.geometry.x)) as i16,
+            (i64::from(top) - i64::from(window.geometry.y)) as i16,
+            width as u16,
+            height as u16,
+        )
+        .map_err(|e| {
+            eprintln!(
+                "[lg-inspect] warning: GetImage failed for window {}: {e}",
+                window.id
+            );
+            CaptureError::Failed(format!("GetImage failed: {e}"))
+        })?;
+        let layout = self
+            .find_visual(visual)
+            .and_then(|v| PixelLayout::from_visual_type(v).ok())
+            .ok_or_else(|| CaptureError::Failed(format!("unsupported visual {visual:#x}")))?;
+        Ok(Capture::clipped(
+            to_rgba(&image, layout),
+            &window.geometry,
+            left,
+            top,
+        ))
+    }
+}
+
+/// Tracks which client windows changed on screen, for the inspect daemon.
+///
+/// Each client in `_NET_CLIENT_LIST` gets a Damage object (repaints) and a
+/// StructureNotify selection (map, unmap, resize); the root window reports
+/// client list changes so new windows are picked up as they appear.
+pub struct DamageWatcher {
+    conn: RustConnection,
+    root: Window,
+    atoms: Atoms,
+    damages: HashMap<Window, Damage>,
+}
+
+impl DamageWatcher {
+    pub fn connect() -> Result<Self> {
+        let (conn, screen_num) =
+            x11rb::connect(None).context("failed to connect to the X server")?;
+        let root = conn.setup().roots[screen_num].root;
+        conn.xfixes_query_version(5, 0)?
+            .reply()
+            .context("XFIXES extension not available")?;
+        conn.damage_query_version(1, 1)?
+            .reply()
+            .context("DAMAGE extension not available")?;
+        let atoms = Atoms::new(&conn)?
+            .reply()
+            .context("failed to intern EWMH atoms")?;
+        conn.change_window_attributes(
+            root,
+            &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )?;
+        Ok(Self {
+            conn,
+            root,
+            atoms,
+            damages: HashMap::new(),
+        })
+    }
+
+    /// Block on X events forever, adding the id of every window that was
+    /// repainted, resized, mapped or unmapped to `dirty`.
+    pub fn run(mut self, dirty: &Mutex<HashSet<String>>) -> Result<()> {
+        self.sync_clients(dirty)?;
+        loop {
+            let event = self.conn.wait_for_event()?;
+            let changed = match event {
+                Event::DamageNotify(e) => {
+                    // Re-arm: NonEmpty damage only reports again once cleared.
+                    self.conn.damage_subtract(e.damage, NONE, NONE)?;
+                    Some(e.drawable)
+                }
+                Event::ConfigureNotify(e) => Some(e.window),
+                Event::MapNotify(e) => Some(e.window),
+                Event::UnmapNotify(e) => Some(e.window),
+                Event::PropertyNotify(e)
+                    if e.window == self.root && e.atom == self.atoms._NET_CLIENT_LIST =>
+                {
+                    self.sync_clients(dirty)?;
+                    None
+                }
+                // BadWindow/BadDamage for clients that vanished mid-request.
+                _ => None,
+            };
+            if let Some(window) = changed.filter(|w| self.damages.contains_key(w)) {
+                dirty.lock().unwrap().insert(format_window_id(window));
+            }
+            self.conn.flush()?;
+        }
+    }
+
+    /// Start tracking new clients and stop tracking closed ones.
+    fn sync_clients(&mut self, dirty: &Mutex<HashSet<String>>) -> Result<()> {
+        let reply = self
+            .conn
+            .get_property(
+                false,
+                self.root,
+                self.atoms._NET_CLIENT_LIST,
+                AtomEnum::WINDOW,
+                0,
+                MAX_PROPERTY_LEN,
+            )?
+            .reply()?;
+        let clients: HashSet<Window> = reply.value32().into_iter().flatten().collect();
+
+        let closed: Vec<Window> = self
+            .damages
+            .keys()
+            .filter(|w| !clients.contains(w))
+            .copied()
+            .collect();
+        for window in closed {
+            if let Some(damage) = self.damages.remove(&window) {
+                // The window is usually gone already, which frees the damage.
+                let _ = self.conn.damage_destroy(damage);
+            }
+        }
+
+        for &window in &clients {
+            if self.damages.contains_key(&window) {
+                continue;
+            }
+            let damage = self.conn.generate_id()?;
+            self.conn
+                .damage_create(damage, window, ReportLevel::NON_EMPTY)?;
+            self.conn.change_window_attributes(
+                window,
+                &ChangeWindowAttributesAux::new().event_mask(EventMask::STRUCTURE_NOTIFY),
+            )?;
+            self.damages.insert(window, damage);
+            dirty.lock().unwrap().insert(format_window_id(window));
+        }
+        self.conn.flush()?;
+        Ok(())
+    }
+}
+
+/// Parse a `0x`-prefixed hex window id.
+fn parse_window_id(id: &str) -> Option<Window> {
+    u32::from_str_radix(id.trim_start_matches("0x"), 16).ok()
+}
+
+fn to_rgba(image: &Image<'_>, layout: PixelLayout) -> RgbaImage {
+    let mut out = RgbaImage::new(u32::from(image.width()), u32::from(image.height()));
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            let (r, g, b) = layout.decode(image.get_pixel(x, y));
+            out.put_pixel(
+                u32::from(x),
+                u32::from(y),
+                Rgba([(r >> 8) as u8, (g >> 8) as u8, (b >> 8) as u8, 255]),
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_window_id() {
+        assert_eq!(parse_window_id("0x03a00003"), Some(0x03a0_0003));
+        assert_eq!(parse_window_id("nonsense"), None);
+    }
+}
@@ -0,0 +1,195 @@
+//! On-disk OCR results keyed by the pixels they were read from.
+//!
+//! Design decision: `PreviousState` only remembers each window's last
+//! capture, so a dialog reopened, a tab switched back or a window restored
+//! after minimizing was OCR'd again although tesseract had read those exact
+//! pixels before. This cache maps the SHA256 of a capture's raw pixels to its
+//! OCR result, one JSON file per entry in `/shared/ocr-cache`, so it outlives
+//! one-shot runs and daemon restarts alike. Element boxes are stored
+//! relative to the captured image, so an entry is valid wherever the same
+//! pixels turn up. The directory is kept under a size budget by evicting the
+//! least recently used entries (by mtime, which a hit refreshes) after each
+//! inspection that wrote to it. Results read with non-default OCR settings
+//! are stored under the hash and a fingerprint of the settings.
+
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use anyhow::Result;
+
+use crate::ocr::OcrSettings;
+use crate::OcrResult;
+
+pub const CACHE_DIR: &str = "/shared/ocr-cache";
+
+/// An entry is a few KB, so this holds thousands of windows.
+pub const DEFAULT_MAX_BYTES: u64 = 32 * 1024 * 1024;
+
+/// Distinguishes temp files of concurrent writers within this process.
+static NEXT_TEMP: AtomicUsize = AtomicUsize::new(0);
+
+pub struct OcrCache {
+    dir: PathBuf,
+    /// Size budget for the entries; 0 disables the cache.
+    max_bytes: u64,
+    /// Something was stored since the last `trim`.
+    written: AtomicBool,
+}
+
+/// The entry name for pixels hashing to `hash` read with `settings`.
+pub fn key(hash: &str, settings: &OcrSettings) -> String {
+    match settings.fingerprint() {
+        fingerprint if fingerprint.is_empty() => hash.to_string(),
+        fingerprint => format!("{hash}-{fingerprint}"),
+    }
+}
+
+impl Default for OcrCache {
+    fn default() -> Self {
+        Self::new(CACHE_DIR, DEFAULT_MAX_BYTES)
+    }
+}
+
+impl OcrCache {
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        Self {
+            dir: dir.into(),
+            max_bytes,
+            written: AtomicBool::new(false),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self::new(PathBuf::new(), 0)
+    }
+
+    pub fn set_max_bytes(&mut self, max_bytes: u64) {
+        self.max_bytes = max_bytes;
+    }
+
+    fn path(&self, hash: &str) -> PathBuf {
+        self.dir.join(format!("{hash}.json"))
+    }
+
+    /// The result stored for `hash`, with image-relative elements.
+    pub fn get(&self, hash: &str) -> Option<OcrResult> {
+        if self.max_bytes == 0 {
+            return None;
+        }
+        let path = self.path(hash);
+        // A corrupt entry is a miss; the fresh OCR overwrites it.
+        let result = serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+        // Mark it recently used for eviction.
+        if let Ok(file) = File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(result)
+    }
+
+    /// Store `result` (with image-relative elements) under `hash`.
+    pub fn put(&self, hash: &str, result: &OcrResult) {
+        if self.max_bytes == 0 {
+            return;
+        }
+        match self.write(hash, result) {
+            Ok(()) => self.written.store(true, Ordering::Relaxed),
+            Err(e) => eprintln!("[lg-inspect] warning: failed to write OCR cache entry: {e:#}"),
+        }
+    }
+
+    fn write(&self, hash: &str, result: &OcrResult) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // OCR workers and other processes may store the same pixels at
+        // once; each writes its own temp file and the rename is atomic.
+        let temp = self.dir.join(format!(
+            "{hash}.{}-{}.tmp",
+            std::process::id(),
+            NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&temp, serde_json::to_string(result)?)?;
+        fs::rename(&temp, self.path(hash))?;
+        Ok(())
+    }
+
+    /// Evict the least recently used entries until the cache fits its
+    /// budget. Does nothing unless something was stored since the last call.
+    pub fn trim(&self) {
+        if !self.written.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        if let Err(e) = evict(&self.dir, self.max_bytes) {
+            eprintln!("[lg-inspect] warning: failed to trim the OCR cache: {e}");
+        }
+    }
+}
+
+fn evict(dir: &Path, max_bytes: u64) -> std::io::Result<()> {
+    let mut entries = Vec::new();
+    let mut total = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        // Another process may have evicted it meanwhile.
+        let Ok(meta) = fs::metadata(&path) else {
+            continue;
+        };
+        total += meta.len();
+        entries.push((meta.modified()?, meta.len(), path));
+    }
+    entries.sort();
+    for (_, len, path) in entries {
+        if total <= max_bytes {
+            break;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => total -= len,
+            Err(e) if e.kind() == ErrorKind::NotFound => total -= len,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn result(text: &str) -> OcrResult {
+        OcrResult {
+            text: text.to_string(),
+            ..OcrResult::default()
+        }
+    }
+
+    fn age(cache: &OcrCache, hash: &str, seconds: u64) {
+        let file = File::options().append(true).open(cache.path(hash)).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(seconds))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let dir = crate::fake::scratch_dir("ocr-cache");
+        let size = serde_json::to_string(&result("a")).unwrap().len() as u64;
+        let cache = OcrCache::new(&dir, 2 * size);
+        for (hash, seconds) in [("a", 30), ("b", 20), ("c", 10)] {
+            cache.put(hash, &result(hash));
+            age(&cache, hash, seconds);
+        }
+        // A hit makes the oldest entry the most recently used.
+        assert_eq!(cache.get("a").unwrap().text, "a");
+        cache.trim();
+
+        assert!(cache.get("b").is_none());
+        assert_eq!(cache.get("c").unwrap().text, "c");
+        assert!(cache.get("a").is_some());
+        assert!(OcrCache::disabled().get("a").is_none());
+    }
+}
@@ -0,0 +1,425 @@
+//! Deciding whether a window's capture changed in a way worth re-OCRing.
+//!
+//! Design decision: hashing the PNG bytes marked a window as changed for a
+//! blinking caret, a status-bar clock or a spinner, forcing a full re-OCR and
+//! telling the agent something happened when nothing did. Captures are now
+//! compared as decoded pixels, with three modes:
+//!
+//! - `exact`: SHA256 of the pixels (after masking ignore-regions).
+//! - `pixel`: changed if more than `tolerance` (a fraction) of the pixels
+//!   differ from the last change that was read by more than
+//!   `pixel_threshold` in any channel. Good for carets and anti-aliasing
+//!   noise.
+//! - `phash`: 64-bit difference hash; changed if more than `phash_distance`
+//!   bits flip. Ignores small local changes altogether, including real ones,
+//!   so it suits windows where only layout changes matter.
+//!
+//! In `pixel` and `phash` mode an unchanged verdict keeps the previous hash,
+//! so the comparison baseline (and the cached OCR keyed by it) only moves when
+//! a change is reported. Small changes therefore cannot creep past the
+//! tolerance one frame at a time.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use image::{imageops, GrayImage, Rgba, RgbaImage};
+use sha2::{Digest, Sha256};
+
+use crate::ListedWindow;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ChangeMode {
+    #[default]
+    Exact,
+    Pixel,
+    Perceptual,
+}
+
+impl ChangeMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "exact" => Some(Self::Exact),
+            "pixel" => Some(Self::Pixel),
+            "phash" => Some(Self::Perceptual),
+            _ => None,
+        }
+    }
+}
+
+/// A rectangle inside matching windows whose pixels never count as change.
+///
+/// Parsed from `<window>:<x>,<y>,<w>,<h>`. `<window>` is a window id, a
+/// case-insensitive title substring, or `*` for every window. Negative `x`/`y`
+/// are measured from the right/bottom edge, so `*:-120,0,120,24` masks a clock
+/// in the top-right corner regardless of window size.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IgnoreRegion {
+    window: String,
+    x: i32,
+    y: i32,
+    w: u32,
+    h: u32,
+}
+
+impl IgnoreRegion {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let Some((window, rect)) = spec.rsplit_once(':') else {
+            bail!("ignore region {spec:?} is not <window>:<x>,<y>,<w>,<h>");
+        };
+        let parts: Vec<&str> = rect.split(',').map(str::trim).collect();
+        if parts.len() != 4 {
+            bail!("ignore region {spec:?} needs exactly x,y,w,h");
+        }
+        let parse_err = || format!("ignore region {spec:?} has a non-numeric coordinate");
+        Ok(Self {
+            window: window.to_string(),
+            x: parts[0].parse().with_context(parse_err)?,
+            y: parts[1].parse().with_context(parse_err)?,
+            w: parts[2].parse().with_context(parse_err)?,
+            h: parts[3].parse().with_context(parse_err)?,
+        })
+    }
+
+    fn matches(&self, window: &ListedWindow) -> bool {
+        self.window == "*"
+            || self.window == window.id
+            || window
+                .title
+                .to_lowercase()
+                .contains(&self.window.to_lowercase())
+    }
+
+    /// The region clipped to a `width` x `height` image, if it overlaps it.
+    fn rect(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        let resolve = |offset: i32, extent: u32| {
+            if offset < 0 {
+                i64::from(extent) + i64::from(offset)
+            } else {
+                i64::from(offset)
+            }
+        };
+        let left = resolve(self.x, width).max(0);
+        let top = resolve(self.y, height).max(0);
+        let right = (resolve(self.x, width) + i64::from(self.w)).min(i64::from(width));
+        let bottom = (resolve(self.y, height) + i64::from(self.h)).min(i64::from(height));
+        if right <= left || bottom <= top {
+            return None;
+        }
+        Some((
+            left as u32,
+            top as u32,
+            (right - left) as u32,
+            (bottom - top) as u32,
+        ))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ChangeConfig {
+    pub mode: ChangeMode,
+    /// Per-channel difference at or below which a pixel counts as equal.
+    pub pixel_threshold: u8,
+    /// Fraction of differing pixels tolerated in `pixel` mode.
+    pub tolerance: f64,
+    /// Hamming distance tolerated in `phash` mode.
+    pub phash_distance: u32,
+    pub ignore: Vec<IgnoreRegion>,
+}
+
+impl Default for ChangeConfig {
+    fn default() -> Self {
+        Self {
+            mode: ChangeMode::Exact,
+            pixel_threshold: 24,
+            tolerance: 0.001,
+            phash_distance: 4,
+            ignore: Vec::new(),
+        }
+    }
+}
+
+/// Outcome of comparing a capture with the window's previous one.
+pub struct Verdict {
+    pub changed: bool,
+    /// Hash to store for the window (the previous one if unchanged).
+    pub hash: String,
+    /// `content_hash` of the capture as is, ignore-regions included, which
+    /// keys the OCR cache.
+    pub content: String,
+}
+
+impl ChangeConfig {
+    /// Judge `image` against the window's previous hash and, in `pixel` mode,
+    /// the previous capture stored at `baseline`.
+    pub fn judge(
+        &self,
+        window: &ListedWindow,
+        image: &RgbaImage,
+        previous_hash: Option<&str>,
+        baseline: &Path,
+    ) -> Verdict {
+        let masked = self.mask(window, image);
+        // One SHA256 pass serves both purposes unless regions were masked.
+        let content = content_hash(image);
+        let hash = match (self.mode, &masked) {
+            (ChangeMode::Perceptual, _) => format!("{:016x}", dhash(&masked)),
+            (_, Cow::Borrowed(_)) => content.clone(),
+            (_, Cow::Owned(masked)) => content_hash(masked),
+        };
+        let Some(previous_hash) = previous_hash else {
+            return Verdict {
+                changed: true,
+                hash,
+                content,
+            };
+        };
+        if previous_hash == hash {
+            return Verdict {
+                changed: false,
+                hash,
+                content,
+            };
+        }
+
+        let within_tolerance = match self.mode {
+            ChangeMode::Exact => false,
+            ChangeMode::Pixel => image::open(baseline).is_ok_and(|previous| {
+                let previous = previous.to_rgba8();
+                previous.dimensions() == image.dimensions()
+                    && fraction_changed(
+                        &self.mask(window, &previous),
+                        &masked,
+                        self.pixel_threshold,
+                    ) <= self.tolerance
+            }),
+            ChangeMode::Perceptual => u64::from_str_radix(previous_hash, 16)
+                .is_ok_and(|p| (p ^ dhash(&masked)).count_ones() <= self.phash_distance),
+        };
+        if within_tolerance {
+            Verdict {
+                changed: false,
+                hash: previous_hash.to_string(),
+                content,
+            }
+        } else {
+            Verdict {
+                changed: true,
+                hash,
+                content,
+            }
+        }
+    }
+
+    /// Black out this window's ignore-regions.
+    fn mask<'a>(&self, window: &ListedWindow, image: &'a RgbaImage) -> Cow<'a, RgbaImage> {
+        let mut rects = self
+            .ignore
+            .iter()
+            .filter(|r| r.matches(window))
+            .filter_map(|r| r.rect(image.width(), image.height()))
+            .peekable();
+        if rects.peek().is_none() {
+            return Cow::Borrowed(image);
+        }
+        let mut masked = image.clone();
+        for (left, top, width, height) in rects {
+            for y in top..top + height {
+                for x in left..left + width {
+                    masked.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+                }
+            }
+        }
+        Cow::Owned(masked)
+    }
+}
+
+/// SHA256 over the dimensions and raw pixels.
+pub fn content_hash(image: &RgbaImage) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(image.width().to_le_bytes());
+    hasher.update(image.height().to_le_bytes());
+    hasher.update(image.as_raw());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Fraction of pixels whose largest channel difference exceeds `threshold`.
+fn fraction_changed(a: &RgbaImage, b: &RgbaImage, threshold: u8) -> f64 {
+    let total = u64::from(a.width()) * u64::from(a.height());
+    if total == 0 {
+        return 0.0;
+    }
+    let changed = a
+        .pixels()
+        .zip(b.pixels())
+        .filter(|(p, q)| {
+            p.0.iter()
+                .zip(q.0.iter())
+                .any(|(x, y)| x.abs_diff(*y) > threshold)
+        })
+        .count();
+    changed as f64 / total as f64
+}
+
+/// 64-bit difference hash: shrink to 9x8 grayscale and record whether each
+/// pixel is brighter than its right neighbour.
+fn dhash(image: &RgbaImage) -> u64 {
+    let gray: GrayImage = imageops::grayscale(image);
+    let small = imageops::resize(&gray, 9, 8, imageops::FilterType::Triangle);
+    let mut bits = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            bits <<= 1;
+            if small.get_pixel(x, y).0[0] > small.get_pixel(x + 1, y).0[0] {
+                bits |= 1;
+            }
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::solid;
+    use crate::{Geometry, WindowMeta};
+
+    fn window(title: &str) -> ListedWindow {
+        ListedWindow {
+            id: "0x00000001".to_string(),
+            geometry: Geometry {
+                x: 0,
+                y: 0,
+                w: 400,
+                h: 300,
+            },
+            title: title.to_string(),
+            desktop: 0,
+            meta: WindowMeta::default(),
+        }
+    }
+
+    /// A white 400x300 window with a black caret at `x`.
+    fn with_caret(x: u32) -> RgbaImage {
+        let mut img = solid(400, 300, [255, 255, 255]);
+        for y in 10..26 {
+            img.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+        }
+        img
+    }
+
+    fn judge(name: &str, config: &ChangeConfig, before: &RgbaImage, after: &RgbaImage) -> bool {
+        let dir = crate::fake::scratch_dir(name);
+        let baseline = dir.join("baseline.png");
+        before.save(&baseline).unwrap();
+        let w = window("Editor");
+        let first = config.judge(&w, before, None, &baseline);
+        config
+            .judge(&w, after, Some(&first.hash), &baseline)
+            .changed
+    }
+
+    #[test]
+    fn test_ignore_region_parse() {
+        let r = IgnoreRegion::parse("Firefox:-120,0,120,24").unwrap();
+        assert_eq!((r.window.as_str(), r.x, r.w), ("Firefox", -120, 120));
+        assert!(IgnoreRegion::parse("Firefox:1,2,3").is_err());
+        assert!(IgnoreRegion::parse("1,2,3,4").is_err());
+    }
+
+    #[test]
+    fn test_ignore_region_negative_offsets() {
+        let r = IgnoreRegion::parse("*:-120,-24,120,24").unwrap();
+        assert_eq!(r.rect(640, 480), Some((520, 456, 120, 24)));
+        assert_eq!(r.rect(100, 20), Some((0, 0, 100, 20)));
+    }
+
+    #[test]
+    fn test_exact_mode_sees_caret() {
+        let config = ChangeConfig::default();
+        assert!(judge(
+            "exact-caret",
+            &config,
+            &with_caret(50),
+            &with_caret(51)
+        ));
+        assert!(!judge(
+            "exact-same",
+            &config,
+            &with_caret(50),
+            &with_caret(50)
+        ));
+    }
+
+    #[test]
+    fn test_pixel_mode_tolerates_caret() {
+        let config = ChangeConfig {
+            mode: ChangeMode::Pixel,
+            ..ChangeConfig::default()
+        };
+        assert!(!judge(
+            "pixel-caret",
+            &config,
+            &with_caret(50),
+            &with_caret(51)
+        ));
+        // A repaint of most of the window is still a change.
+        assert!(judge(
+            "pixel-repaint",
+            &config,
+            &with_caret(50),
+            &solid(400, 300, [0, 0, 255])
+        ));
+    }
+
+    #[test]
+    fn test_phash_mode_tolerates_caret() {
+        let config = ChangeConfig {
+            mode: ChangeMode::Perceptual,
+            ..ChangeConfig::default()
+        };
+        assert!(!judge(
+            "phash-caret",
+            &config,
+            &with_caret(50),
+            &with_caret(51)
+        ));
+        let mut split = solid(400, 300, [255, 255, 255]);
+        for y in 0..300 {
+            for x in 200..400 {
+                split.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+            }
+        }
+        assert!(judge("phash-split", &config, &with_caret(50), &split));
+    }
+
+    #[test]
+    fn test_ignore_region_masks_exact_mode() {
+        let config = ChangeConfig {
+            ignore: vec![IgnoreRegion::parse("editor:40,0,20,300").unwrap()],
+            ..ChangeConfig::default()
+        };
+        assert!(!judge(
+            "ignore-inside",
+            &config,
+            &with_caret(50),
+            &with_caret(51)
+        ));
+        // Outside the region the caret still counts.
+        assert!(judge(
+            "ignore-outside",
+            &config,
+            &with_caret(50),
+            &with_caret(80)
+        ));
+
+        // The OCR cache key covers the masked-out pixels too.
+        let w = window("Editor");
+        let baseline = Path::new("/nonexistent.png");
+        let masked = config.judge(&w, &with_caret(50), None, baseline);
+        assert_eq!(masked.content, content_hash(&with_caret(50)));
+        assert_ne!(masked.hash, masked.content);
+        let plain = ChangeConfig::default().judge(&w, &with_caret(50), None, baseline);
+        assert_eq!(plain.hash, plain.content);
+    }
+}
@@ -0,0 +1,290 @@
+//! OCR settings: defaults, config file, environment and flags, with
+//! per-window overrides.
+//!
+//! Design decision: one set of tesseract settings cannot suit every window.
+//! A German application needs `deu`, a sparse toolbar is only read with page
+//! segmentation mode 11, and a noisy terminal wants a higher confidence
+//! cutoff. Global settings are layered from lowest to highest precedence:
+//! built-in defaults, the `ocr` section of the config file, `LG_INSPECT_*`
+//! variables, then flags. The config file's `windows` rules are matched like
+//! `--window` (an id, or a regex searched in the title and `WM_CLASS`) and
+//! every matching rule, in file order, overrides the fields it sets on top
+//! of the global settings. The file is JSON like the rest of our state and
+//! is read on every inspection, so the daemon picks up edits without a
+//! restart.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::filter::WindowPattern;
+use crate::ocr::OcrSettings;
+use crate::preprocess::Mode;
+use crate::ListedWindow;
+
+/// Read when `LG_INSPECT_CONFIG` is unset; a missing file is no config.
+pub const CONFIG_PATH: &str = "/shared/lg-inspect.json";
+
+/// Settings fields to override, as they appear in the config file.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct OcrOverrides {
+    languages: Option<String>,
+    min_confidence: Option<f32>,
+    psm: Option<u8>,
+    oem: Option<u8>,
+    preprocess: Option<Mode>,
+}
+
+impl OcrOverrides {
+    fn validate(&self) -> Result<()> {
+        if let Some(value) = &self.languages {
+            languages(value)?;
+        }
+        if let Some(value) = self.min_confidence {
+            check_confidence(value)?;
+        }
+        if let Some(value) = self.psm {
+            check_psm(value)?;
+        }
+        if let Some(value) = self.oem {
+            check_oem(value)?;
+        }
+        Ok(())
+    }
+
+    fn apply(&self, settings: &mut OcrSettings) {
+        if let Some(value) = &self.languages {
+            settings.languages.clone_from(value);
+        }
+        if let Some(value) = self.min_confidence {
+            settings.min_confidence = value;
+        }
+        if self.psm.is_some() {
+            settings.psm = self.psm;
+        }
+        if self.oem.is_some() {
+            settings.oem = self.oem;
+        }
+        if let Some(value) = self.preprocess {
+            settings.preprocess = value;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WindowRule {
+    #[serde(rename = "match")]
+    pattern: String,
+    #[serde(default)]
+    ocr: OcrOverrides,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    ocr: OcrOverrides,
+    #[serde(default)]
+    windows: Vec<WindowRule>,
+}
+
+/// The effective global settings and the per-window rules.
+#[derive(Default)]
+pub struct OcrConfig {
+    pub settings: OcrSettings,
+    windows: Vec<(WindowPattern, OcrOverrides)>,
+}
+
+impl OcrConfig {
+    /// Load the file named by `LG_INSPECT_CONFIG` in `env`, or
+    /// `CONFIG_PATH` if it exists.
+    pub fn load(env: &HashMap<String, String>) -> Result<Self> {
+        let (path, required) = match env.get("LG_INSPECT_CONFIG") {
+            Some(path) => (path.clone(), true),
+            None => (CONFIG_PATH.to_string(), false),
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).with_context(|| format!("invalid config file {path}")),
+            Err(e) if e.kind() == ErrorKind::NotFound && !required => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read config file {path}")),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let file: ConfigFile = serde_json::from_str(text)?;
+        file.ocr.validate()?;
+        let mut settings = OcrSettings::default();
+        file.ocr.apply(&mut settings);
+        let windows = file
+            .windows
+            .into_iter()
+            .map(|rule| {
+                rule.ocr
+                    .validate()
+                    .with_context(|| format!("in the rule for {}", rule.pattern))?;
+                Ok((WindowPattern::parse(&rule.pattern)?, rule.ocr))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { settings, windows })
+    }
+
+    /// Override the global settings from `LG_INSPECT_LANG`,
+    /// `LG_INSPECT_MIN_CONFIDENCE`, `LG_INSPECT_PSM`, `LG_INSPECT_OEM` and
+    /// `LG_INSPECT_PREPROCESS` in `env`.
+    pub fn apply_env(&mut self, env: &HashMap<String, String>) -> Result<()> {
+        let var = |name| env.get(name).cloned();
+        let overrides = OcrOverrides {
+            languages: var("LG_INSPECT_LANG"),
+            min_confidence: var("LG_INSPECT_MIN_CONFIDENCE")
+                .map(|v| confidence(&v).context("invalid LG_INSPECT_MIN_CONFIDENCE"))
+                .transpose()?,
+            psm: var("LG_INSPECT_PSM")
+                .map(|v| psm(&v).context("invalid LG_INSPECT_PSM"))
+                .transpose()?,
+            oem: var("LG_INSPECT_OEM")
+                .map(|v| oem(&v).context("invalid LG_INSPECT_OEM"))
+                .transpose()?,
+            preprocess: var("LG_INSPECT_PREPROCESS")
+                .map(|v| preprocess(&v).context("invalid LG_INSPECT_PREPROCESS"))
+                .transpose()?,
+        };
+        overrides.validate().context("invalid LG_INSPECT_LANG")?;
+        overrides.apply(&mut self.settings);
+        Ok(())
+    }
+
+    /// The settings `window` is read with.
+    pub fn for_window(&self, window: &ListedWindow) -> OcrSettings {
+        let mut settings = self.settings.clone();
+        for (pattern, overrides) in &self.windows {
+            if pattern.matches(window) {
+                overrides.apply(&mut settings);
+            }
+        }
+        settings
+    }
+}
+
+/// Parse a `+`-separated list of tesseract language models.
+pub fn languages(value: &str) -> Result<String> {
+    let valid = |lang: &str| {
+        !lang.is_empty()
+            && lang
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    };
+    if !value.split('+').all(valid) {
+        bail!("{value} is not a +-separated list of languages, e.g. eng+deu");
+    }
+    Ok(value.to_string())
+}
+
+pub fn confidence(value: &str) -> Result<f32> {
+    let parsed = value
+        .parse()
+        .with_context(|| format!("{value} is not a number"))?;
+    check_confidence(parsed)?;
+    Ok(parsed)
+}
+
+pub fn psm(value: &str) -> Result<u8> {
+    let parsed = value
+        .parse()
+        .with_context(|| format!("{value} is not a page segmentation mode (0-13)"))?;
+    check_psm(parsed)?;
+    Ok(parsed)
+}
+
+pub fn oem(value: &str) -> Result<u8> {
+    let parsed = value
+        .parse()
+        .with_context(|| format!("{value} is not an OCR engine mode (0-3)"))?;
+    check_oem(parsed)?;
+    Ok(parsed)
+}
+
+pub fn preprocess(value: &str) -> Result<Mode> {
+    Mode::parse(value).with_context(|| format!("{value} is not off, auto or always"))
+}
+
+fn check_confidence(value: f32) -> Result<()> {
+    if !(0.0..=100.0).contains(&value) {
+        bail!("confidence {value} is outside 0-100");
+    }
+    Ok(())
+}
+
+fn check_psm(value: u8) -> Result<()> {
+    if value > 13 {
+        bail!("page segmentation mode {value} is outside 0-13");
+    }
+    Ok(())
+}
+
+fn check_oem(value: u8) -> Result<()> {
+    if value > 3 {
+        bail!("OCR engine mode {value} is outside 0-3");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Geometry, WindowMeta};
+
+    fn window(title: &str, class: &str) -> ListedWindow {
+        ListedWindow {
+            id: "0x00000001".to_string(),
+            geometry: Geometry {
+                x: 0,
+                y: 0,
+                w: 100,
+                h: 100,
+            },
+            title: title.to_string(),
+            desktop: 0,
+            meta: WindowMeta {
+                class: class.to_string(),
+                ..WindowMeta::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_window_rules_override_global_settings() {
+        let config = OcrConfig::parse(
+            r#"{
+                "ocr": {"languages": "eng", "min_confidence": 50},
+                "windows": [
+                    {"match": "^Gimp$", "ocr": {"languages": "deu"}},
+                    {"match": "Toolbar", "ocr": {"psm": 11}}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.settings.languages, "eng");
+        assert_eq!(config.for_window(&window("Terminal", "")), config.settings);
+
+        // Both rules match; each sets its own field.
+        let toolbar = config.for_window(&window("Toolbar", "Gimp"));
+        assert_eq!(toolbar.languages, "deu");
+        assert_eq!(toolbar.psm, Some(11));
+        assert!((toolbar.min_confidence - 50.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn test_invalid_config_is_rejected() {
+        assert!(OcrConfig::parse(r#"{"ocr": {"psm": 14}}"#).is_err());
+        assert!(OcrConfig::parse(r#"{"ocr": {"lang": "eng"}}"#).is_err());
+        let rule = r#"{"windows": [{"match": "x", "ocr": {"languages": "eng deu"}}]}"#;
+        assert!(OcrConfig::parse(rule).is_err());
+        assert!(confidence("101").is_err());
+        assert_eq!(languages("chi_sim+eng").unwrap(), "chi_sim+eng");
+    }
+}
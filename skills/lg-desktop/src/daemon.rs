@@ -0,0 +1,420 @@
+//! `lg-inspect --daemon`: a long-running inspector driven by X damage events.
+//!
+//! Design decision: a one-shot `lg-inspect` has to capture and hash every
+//! window because it cannot know what happened since the last run. The daemon
+//! keeps the previous state in memory and subscribes to Damage, map and
+//! configure events, so a query only captures the windows the X server says
+//! were repainted. Queries arrive over a Unix socket in `/shared` (visible to
+//! `docker exec` and to the host mount) as one JSON line carrying the client's
+//! argument list and `LG_INSPECT_*` variables (including the config file
+//! path), which the daemon parses in place of its own; the reply is the same
+//! JSON a one-shot run would print. Only the OCR engine is the daemon's, and
+//! `--backend shell` clients never query it, as the daemon captures through
+//! X11. Clients fall back to inspecting in-process whenever no daemon
+//! answers in full (none listening, or one that hangs, dies or cuts its reply
+//! short), so the daemon is purely an accelerator; only an error the daemon
+//! reports about the query itself is passed on. One-shot runs
+//! (`--no-daemon`, `--backend shell`) still save their state; the daemon
+//! notices the file changed under it and starts over from that state, with a
+//! full capture, instead of overwriting it. A query filtered with
+//! `--window`/`--exclude` leaves the damage of the windows it skipped queued,
+//! so the next query that selects them still captures them.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::x11::{DamageWatcher, X11Backend};
+use crate::backend::{BackendChoice, DesktopBackend};
+use crate::ocr::{self, OcrEngine};
+use crate::{
+    inspect, load_previous_state, parse_args, run_i3_commands, save_state, state_modified, Env,
+    InspectOutput, Options, PreviousState, SCREENSHOT_DIR,
+};
+
+pub const SOCKET_PATH: &str = "/shared/lg-inspect.sock";
+
+/// How long a client waits for the daemon (OCR of a busy desktop is slow).
+const QUERY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long the daemon waits on a client's request or for it to take the
+/// reply. Queries are served one at a time, so a stalled client must not
+/// hold up the rest.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Requests are an argument list and a few variables; anything longer is
+/// not a client of ours.
+const MAX_REQUEST_BYTES: u64 = 64 * 1024;
+
+#[derive(Serialize, Deserialize)]
+struct Request {
+    args: Vec<String>,
+    /// The client's `LG_INSPECT_*` variables, which stand in for ours.
+    #[serde(default)]
+    env: Env,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ErrorReply {
+    error: String,
+}
+
+struct Daemon {
+    backend: Box<dyn DesktopBackend>,
+    /// Set up once, so libtesseract's models stay loaded across queries.
+    engine: Box<dyn OcrEngine>,
+    previous: PreviousState,
+    /// Windows reported changed by the watcher, or left out by a filtered
+    /// query, since they were last captured.
+    dirty: Arc<Mutex<HashSet<String>>>,
+    /// False until the first query has captured; before that we cannot
+    /// trust `previous` (it was loaded from disk).
+    primed: bool,
+    /// Modification time of the state file as we last read or wrote it.
+    state_modified: Option<SystemTime>,
+}
+
+/// Run the daemon until the X connection dies. `options` are the daemon's
+/// own; its OCR engine serves every query.
+pub fn run(options: &Options) -> Result<()> {
+    let listener = bind(Path::new(SOCKET_PATH))?;
+    let dirty = Arc::new(Mutex::new(HashSet::new()));
+
+    let watcher = DamageWatcher::connect()?;
+    let watcher_dirty = Arc::clone(&dirty);
+    thread::spawn(move || {
+        if let Err(e) = watcher.run(&watcher_dirty) {
+            // Without events our idea of "clean" is stale; exit so clients
+            // fall back to one-shot inspection instead of trusting us.
+            eprintln!("[lg-inspect] error: X event watcher stopped: {e:#}");
+            let _ = fs::remove_file(SOCKET_PATH);
+            std::process::exit(1);
+        }
+    });
+
+    let mut daemon = Daemon {
+        backend: Box::new(X11Backend::connect()?),
+        engine: ocr::engine(options.ocr_engine, &options.ocr.settings)?,
+        previous: load_previous_state(),
+        dirty,
+        primed: false,
+        state_modified: state_modified(),
+    };
+    eprintln!("[lg-inspect] daemon listening on {SOCKET_PATH}");
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = daemon.serve(&stream) {
+                    eprintln!("[lg-inspect] warning: daemon query failed: {e:#}");
+                }
+            }
+            Err(e) => eprintln!("[lg-inspect] warning: daemon accept failed: {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// Bind the socket, clearing a stale one left by a crashed daemon.
+fn bind(path: &Path) -> Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            bail!(
+                "an lg-inspect daemon is already listening on {}",
+                path.display()
+            );
+        }
+        fs::remove_file(path)
+            .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    UnixListener::bind(path).with_context(|| format!("failed to bind {}", path.display()))
+}
+
+impl Daemon {
+    fn serve(&mut self, mut stream: &UnixStream) -> Result<()> {
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        let mut line = String::new();
+        BufReader::new(stream.take(MAX_REQUEST_BYTES)).read_line(&mut line)?;
+        if !line.ends_with('\n') {
+            bail!("request is incomplete or longer than {MAX_REQUEST_BYTES} bytes");
+        }
+        let reply = match self.answer(&line) {
+            Ok(json) => json,
+            Err(e) => serde_json::to_string(&ErrorReply {
+                error: format!("{e:#}"),
+            })?,
+        };
+        stream.write_all(reply.as_bytes())?;
+        Ok(())
+    }
+
+    fn answer(&mut self, line: &str) -> Result<String> {
+        let request: Request = serde_json::from_str(line).context("malformed daemon request")?;
+        let options = parse_args(request.args, &request.env)?;
+        if options.daemon {
+            bail!("--daemon cannot be sent to a running daemon");
+        }
+        if options.backend == BackendChoice::Shell {
+            bail!("the daemon captures through X11 only; use --no-daemon for --backend shell");
+        }
+        run_i3_commands(&options)?;
+        // A one-shot run saved its state since our last query: take it over,
+        // and capture everything as our damage predates it.
+        if state_modified() != self.state_modified {
+            self.previous = load_previous_state();
+            self.primed = false;
+        }
+        let output = self.inspect(&options, Path::new(SCREENSHOT_DIR));
+        // Keep the state file current so one-shot runs agree with us.
+        if let Err(e) = save_state(&self.previous) {
+            eprintln!("[lg-inspect] warning: failed to save state: {e}");
+        }
+        self.state_modified = state_modified();
+        Ok(serde_json::to_string_pretty(&output)?)
+    }
+
+    fn inspect(&mut self, options: &Options, screenshot_dir: &Path) -> InspectOutput {
+        // Take the dirty set before capturing: damage that lands while we
+        // capture stays queued for the next query instead of being lost.
+        let damaged = std::mem::take(&mut *self.dirty.lock().unwrap());
+        let (output, new_state) = inspect(
+            self.backend.as_mut(),
+            self.engine.as_ref(),
+            &self.previous,
+            options,
+            screenshot_dir,
+            self.primed.then_some(&damaged),
+        );
+        // Windows the filter left out were not captured, so their damage is
+        // still pending; before priming, so is whatever changed on disk.
+        // Windows that could not be captured or read kept their old state,
+        // so they must be captured again even without new damage.
+        let left_out = output
+            .left_out
+            .iter()
+            .filter(|id| !self.primed || damaged.contains(*id));
+        let failed = output
+            .windows
+            .iter()
+            .chain(&output.popups)
+            .filter(|w| w.status.failed())
+            .map(|w| &w.id);
+        self.dirty
+            .lock()
+            .unwrap()
+            .extend(left_out.chain(failed).cloned());
+        self.primed = true;
+        self.previous = new_state;
+        output
+    }
+}
+
+/// Forward an inspection to a running daemon.
+///
+/// Returns `None` when no daemon gives a complete reply, so the caller
+/// inspects itself.
+pub fn query(args: &[String], env: &Env) -> Option<Result<String>> {
+    query_socket(Path::new(SOCKET_PATH), args, env)
+}
+
+fn query_socket(path: &Path, args: &[String], env: &Env) -> Option<Result<String>> {
+    let stream = UnixStream::connect(path).ok()?;
+    let reply = match send(&stream, args, env) {
+        Ok(reply) => reply,
+        Err(e) => {
+            eprintln!(
+                "[lg-inspect] warning: no usable reply from daemon, inspecting in-process: {e:#}"
+            );
+            return None;
+        }
+    };
+    Some(match serde_json::from_str(&reply) {
+        Ok(ErrorReply { error }) => Err(anyhow!("daemon: {error}")),
+        Err(_) => Ok(reply),
+    })
+}
+
+/// Send the query and read the whole reply, which must be complete JSON.
+fn send(mut stream: &UnixStream, args: &[String], env: &Env) -> Result<String> {
+    stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    let request = serde_json::to_string(&Request {
+        args: args.to_vec(),
+        env: env.clone(),
+    })?;
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(b"\n")?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+
+    let mut reply = String::new();
+    stream
+        .read_to_string(&mut reply)
+        .context("no reply from lg-inspect daemon")?;
+    serde_json::from_str::<serde::de::IgnoredAny>(&reply)
+        .context("incomplete reply from lg-inspect daemon")?;
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{apply_flags, cache, fake, WindowStatus};
+
+    fn daemon(desktop: fake::FakeDesktop, previous: PreviousState) -> Daemon {
+        Daemon {
+            backend: Box::new(desktop),
+            engine: Box::new(fake::fixture_ocr()),
+            previous,
+            dirty: Arc::default(),
+            primed: false,
+            state_modified: None,
+        }
+    }
+
+    fn options(args: &[&str]) -> Options {
+        let options = Options {
+            jobs: Some(1),
+            ocr_cache: cache::OcrCache::disabled(),
+            ..Options::default()
+        };
+        apply_flags(options, args.iter().map(ToString::to_string)).unwrap()
+    }
+
+    #[test]
+    fn test_filtered_query_keeps_damage_of_skipped_windows() {
+        let dir = fake::scratch_dir("daemon-filter");
+        let mut daemon = daemon(fake::two_window_desktop(), PreviousState::default());
+        daemon.inspect(&options(&[]), &dir);
+
+        // The dialog is repainted, but the next query only asks for the
+        // terminal.
+        let mut desktop = fake::two_window_desktop();
+        desktop.paint("0x00000002", fake::solid(400, 200, [200, 200, 200]));
+        daemon.backend = Box::new(desktop);
+        daemon
+            .dirty
+            .lock()
+            .unwrap()
+            .insert("0x00000002".to_string());
+        let output = daemon.inspect(&options(&["--window", "^Term"]), &dir);
+        assert_eq!(output.windows.len(), 1);
+        assert!(output.changes_since_last.is_empty());
+
+        let output = daemon.inspect(&options(&[]), &dir);
+        assert_eq!(output.changes_since_last, ["0x00000002"]);
+        assert!(daemon.dirty.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unprimed_filtered_query_leaves_skipped_windows_dirty() {
+        // State saved by an earlier run, before the dialog was repainted
+        // while no daemon was watching.
+        let dir = fake::scratch_dir("daemon-unprimed");
+        let mut startup = daemon(fake::two_window_desktop(), PreviousState::default());
+        startup.inspect(&options(&[]), &dir);
+        let mut desktop = fake::two_window_desktop();
+        desktop.paint("0x00000002", fake::solid(400, 200, [200, 200, 200]));
+        let mut daemon = daemon(desktop, startup.previous);
+
+        daemon.inspect(&options(&["--window", "^Term"]), &dir);
+        let output = daemon.inspect(&options(&[]), &dir);
+        assert_eq!(output.changes_since_last, ["0x00000002"]);
+    }
+
+    #[test]
+    fn test_failed_ocr_is_retried_by_the_next_query() {
+        let dir = fake::scratch_dir("daemon-ocr-failure");
+        let mut daemon = daemon(fake::two_window_desktop(), PreviousState::default());
+        daemon.inspect(&options(&[]), &dir);
+
+        let mut desktop = fake::two_window_desktop();
+        desktop.paint("0x00000001", fake::solid(640, 480, [40, 40, 40]));
+        daemon.backend = Box::new(desktop);
+        daemon
+            .dirty
+            .lock()
+            .unwrap()
+            .insert("0x00000001".to_string());
+        daemon.engine = Box::new(fake::FakeOcr::default());
+        let output = daemon.inspect(&options(&[]), &dir);
+        assert_eq!(output.windows[0].status, WindowStatus::OcrFailed);
+        assert!(output.changes_since_last.is_empty());
+
+        // No new damage, but the change was never read.
+        daemon.engine = Box::new(fake::fixture_ocr());
+        let output = daemon.inspect(&options(&[]), &dir);
+        assert_eq!(output.windows[0].status, WindowStatus::Ok);
+        assert_eq!(output.changes_since_last, ["0x00000001"]);
+    }
+
+    /// A socket at `path` whose one connection reads the query and gets
+    /// `reply`.
+    fn reply_once(path: &Path, reply: &'static str) -> thread::JoinHandle<()> {
+        let listener = bind(path).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            stream.write_all(reply.as_bytes()).unwrap();
+        })
+    }
+
+    #[test]
+    fn test_query_falls_back_without_a_complete_reply() {
+        let dir = fake::scratch_dir("daemon-query");
+        let socket = dir.join("lg-inspect.sock");
+        let args = ["--changes-only".to_string()];
+        assert!(query_socket(&socket, &args, &Env::new()).is_none());
+
+        for broken in ["", r#"{"timestamp":"2025-01-01T00:00:00Z","win"#] {
+            let daemon = reply_once(&socket, broken);
+            assert!(query_socket(&socket, &args, &Env::new()).is_none());
+            daemon.join().unwrap();
+        }
+
+        let daemon = reply_once(&socket, r#"{"error":"unknown argument: --bogus"}"#);
+        let reply = query_socket(&socket, &args, &Env::new()).unwrap();
+        assert!(reply.unwrap_err().to_string().contains("--bogus"));
+        daemon.join().unwrap();
+
+        let output = r#"{"timestamp":"2025-01-01T00:00:00Z","windows":[]}"#;
+        let daemon = reply_once(&socket, output);
+        let reply = query_socket(&socket, &args, &Env::new()).unwrap();
+        assert_eq!(reply.unwrap(), output);
+        daemon.join().unwrap();
+    }
+
+    #[test]
+    fn test_serve_rejects_oversized_request() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let flood = thread::spawn(move || {
+            // Never a newline; the write fails once the daemon hangs up.
+            let bytes = vec![b'x'; 2 * usize::try_from(MAX_REQUEST_BYTES).unwrap()];
+            let _ = (&client).write_all(&bytes);
+        });
+        let mut daemon = daemon(fake::two_window_desktop(), PreviousState::default());
+        let error = daemon.serve(&server).unwrap_err();
+        assert!(error.to_string().contains("longer than"));
+        drop(server);
+        flood.join().unwrap();
+    }
+
+    #[test]
+    fn test_error_reply_is_not_confused_with_output() {
+        let output = r#"{"timestamp":"2025-01-01T00:00:00Z","windows":[]}"#;
+        assert!(serde_json::from_str::<ErrorReply>(output).is_err());
+        let error = r#"{"error":"unknown argument: --bogus"}"#;
+        assert!(serde_json::from_str::<ErrorReply>(error).is_ok());
+    }
+}
@@ -0,0 +1,225 @@
+//! Element-level diff between two OCR results of the same window.
+//!
+//! Design decision: `--changes-only` cut the output to changed windows, but a
+//! one-word change in an editor still printed the whole window's text and
+//! every element. For "did my click work?" checks the agent only needs what
+//! appeared, disappeared or moved, so `--diff` replaces `ocr_text`/`elements`
+//! with this diff.
+//!
+//! Elements are matched by text. A previous element with the same text within
+//! `SAME_PLACE` pixels is unchanged (OCR boxes jitter by a pixel or two
+//! between runs); otherwise the nearest same-text element counts as moved.
+//! Comparison happens in window-relative coordinates so a window that moved
+//! and changed does not report every element as moved; positions in the diff
+//! are absolute, using the window's current position. Lines are compared as
+//! multisets, which is enough to spot new and vanished lines without the
+//! cost and noise of a full sequence diff over OCR output.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Geometry, OcrResult, TextElement};
+
+/// Largest per-axis offset still considered the same position.
+const SAME_PLACE: i32 = 3;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ElementDiff {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub added: Vec<TextElement>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<TextElement>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub moved: Vec<MovedElement>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub added_lines: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed_lines: Vec<String>,
+}
+
+/// An element at its new position, with the absolute point it moved from.
+#[derive(Serialize, Deserialize)]
+pub struct MovedElement {
+    #[serde(flatten)]
+    pub element: TextElement,
+    pub from: [i32; 2],
+}
+
+/// Diff `current` against `previous` (both window-relative), reporting
+/// absolute coordinates for a window at `origin`.
+///
+/// A window with no previous result diffs against nothing, so all of its
+/// text is reported as added.
+pub fn diff(previous: Option<&OcrResult>, current: &OcrResult, origin: &Geometry) -> ElementDiff {
+    let empty = OcrResult::default();
+    let previous = previous.unwrap_or(&empty);
+    let absolute = |e: &TextElement| TextElement {
+        x: e.x + origin.x,
+        y: e.y + origin.y,
+        ..e.clone()
+    };
+
+    let mut unmatched: Vec<&TextElement> = previous.elements.iter().collect();
+    let mut fresh = Vec::new();
+    for e in &current.elements {
+        let same = unmatched.iter().position(|p| {
+            p.text == e.text && (p.x - e.x).abs() <= SAME_PLACE && (p.y - e.y).abs() <= SAME_PLACE
+        });
+        match same {
+            Some(i) => {
+                unmatched.swap_remove(i);
+            }
+            None => fresh.push(e),
+        }
+    }
+
+    let mut result = ElementDiff::default();
+    for e in fresh {
+        let nearest = unmatched
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.text == e.text)
+            .min_by_key(|(_, p)| (i64::from(p.x - e.x)).pow(2) + (i64::from(p.y - e.y)).pow(2))
+            .map(|(i, _)| i);
+        match nearest {
+            Some(i) => {
+                let from = unmatched.swap_remove(i);
+                result.moved.push(MovedElement {
+                    element: absolute(e),
+                    from: [from.x + origin.x, from.y + origin.y],
+                });
+            }
+            None => result.added.push(absolute(e)),
+        }
+    }
+    result.removed = unmatched.into_iter().map(absolute).collect();
+    result.removed.sort_by_key(|e| (e.y, e.x));
+
+    let mut old_lines: HashMap<&str, usize> = HashMap::new();
+    for line in previous.text.lines() {
+        *old_lines.entry(line).or_default() += 1;
+    }
+    for line in current.text.lines() {
+        match old_lines.get_mut(line) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => result.added_lines.push(line.to_string()),
+        }
+    }
+    // Removed lines in their original order.
+    for line in previous.text.lines() {
+        if let Some(count) = old_lines.get_mut(line) {
+            if *count > 0 {
+                *count -= 1;
+                result.removed_lines.push(line.to_string());
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::occlusion::Visibility;
+
+    fn word(text: &str, x: i32, y: i32) -> TextElement {
+        TextElement {
+            text: text.to_string(),
+            x,
+            y,
+            w: 40,
+            h: 14,
+            confidence: 90.0,
+            visibility: Visibility::Visible,
+            id: None,
+        }
+    }
+
+    fn result(text: &str, elements: Vec<TextElement>) -> OcrResult {
+        OcrResult {
+            text: text.to_string(),
+            elements,
+            ..OcrResult::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_reports_added_removed_and_moved() {
+        let before = result(
+            "File Edit\nSaving...",
+            vec![
+                word("File", 0, 0),
+                word("Edit", 50, 0),
+                word("Saving...", 10, 100),
+            ],
+        );
+        let after = result(
+            "File Edit\nSaved",
+            vec![
+                word("File", 1, 1),
+                word("Edit", 50, 40),
+                word("Saved", 10, 100),
+            ],
+        );
+        let origin = Geometry {
+            x: 100,
+            y: 200,
+            w: 400,
+            h: 300,
+        };
+        let diff = diff(Some(&before), &after, &origin);
+
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(
+            (diff.added[0].text.as_str(), diff.added[0].x),
+            ("Saved", 110)
+        );
+        assert_eq!(diff.removed[0].text, "Saving...");
+        assert_eq!(diff.moved.len(), 1);
+        assert_eq!(diff.moved[0].from, [150, 200]);
+        assert_eq!(diff.moved[0].element.y, 240);
+        assert_eq!(diff.added_lines, ["Saved"]);
+        assert_eq!(diff.removed_lines, ["Saving..."]);
+    }
+
+    #[test]
+    fn test_diff_without_previous_adds_everything() {
+        let after = result("OK", vec![word("OK", 5, 5)]);
+        let origin = Geometry {
+            x: 0,
+            y: 0,
+            w: 100,
+            h: 100,
+        };
+        let diff = diff(None, &after, &origin);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added_lines, ["OK"]);
+        assert!(diff.removed.is_empty() && diff.moved.is_empty());
+    }
+
+    #[test]
+    fn test_diff_repeated_lines_count() {
+        let before = result("> ls\n> ls", Vec::new());
+        let after = result("> ls\n> ls\n> ls", Vec::new());
+        let origin = Geometry {
+            x: 0,
+            y: 0,
+            w: 100,
+            h: 100,
+        };
+        let diff = diff(Some(&before), &after, &origin);
+        assert_eq!(diff.added_lines, ["> ls"]);
+        assert!(diff.removed_lines.is_empty());
+    }
+
+    #[test]
+    fn test_diff_serializes_only_nonempty_parts() {
+        let diff = ElementDiff {
+            added_lines: vec!["Saved".to_string()],
+            ..ElementDiff::default()
+        };
+        let json = serde_json::to_value(&diff).unwrap();
+        assert_eq!(json, serde_json::json!({"added_lines": ["Saved"]}));
+    }
+}
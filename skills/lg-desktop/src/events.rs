@@ -0,0 +1,155 @@
+//! Window lifecycle events between two inspections.
+//!
+//! Design decision: `changes_since_last` lumps new windows together with
+//! repainted ones, and a closed window simply vanished from the output, so an
+//! agent had to diff window lists itself to notice an error dialog popping up
+//! or an application dying. The state file now records every listed window's
+//! title and geometry, and each inspection reports what opened, closed, moved,
+//! resized or changed content. `changes_since_last` is kept as is for
+//! existing callers.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Geometry, ListedWindow};
+
+/// What the state file remembers about each listed window.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WindowRecord {
+    pub title: String,
+    pub geometry: Geometry,
+}
+
+/// A window present at the previous inspection but gone now. It carries its
+/// title because it is no longer in `windows[]` to look the id up.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ClosedWindow {
+    pub id: String,
+    pub title: String,
+}
+
+/// Window ids grouped by what happened to them since the previous inspection.
+/// A window can be both moved and resized, and both of those and
+/// content-changed; an opened window is only reported as opened.
+#[derive(Serialize, Deserialize, Default)]
+pub struct WindowEvents {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub opened: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub closed: Vec<ClosedWindow>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub moved: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resized: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub content_changed: Vec<String>,
+}
+
+impl WindowEvents {
+    /// Compare the current window list with the previous records; the
+    /// caller adds `content_changed` once it has diffed the captures.
+    pub fn from_layout(previous: &HashMap<String, WindowRecord>, windows: &[ListedWindow]) -> Self {
+        let mut events = Self::default();
+        for window in windows {
+            let Some(before) = previous.get(&window.id) else {
+                events.opened.push(window.id.clone());
+                continue;
+            };
+            let (old, new) = (before.geometry, window.geometry);
+            if (old.x, old.y) != (new.x, new.y) {
+                events.moved.push(window.id.clone());
+            }
+            if (old.w, old.h) != (new.w, new.h) {
+                events.resized.push(window.id.clone());
+            }
+        }
+
+        let current: HashSet<&str> = windows.iter().map(|w| w.id.as_str()).collect();
+        events.closed = previous
+            .iter()
+            .filter(|(id, _)| !current.contains(id.as_str()))
+            .map(|(id, record)| ClosedWindow {
+                id: id.clone(),
+                title: record.title.clone(),
+            })
+            .collect();
+        events.closed.sort_by(|a, b| a.id.cmp(&b.id));
+        events
+    }
+
+    /// Record a content change unless the window is already reported as
+    /// opened.
+    pub fn content_changed(&mut self, id: &str) {
+        if !self.opened.iter().any(|o| o == id) {
+            self.content_changed.push(id.to_string());
+        }
+    }
+}
+
+/// Records for the next run's comparison.
+pub fn records(windows: &[ListedWindow]) -> HashMap<String, WindowRecord> {
+    windows
+        .iter()
+        .map(|w| {
+            (
+                w.id.clone(),
+                WindowRecord {
+                    title: w.title.clone(),
+                    geometry: w.geometry,
+                },
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WindowMeta;
+
+    fn window(id: &str, x: i32, w: u32) -> ListedWindow {
+        ListedWindow {
+            id: id.to_string(),
+            geometry: Geometry { x, y: 0, w, h: 100 },
+            title: format!("title of {id}"),
+            desktop: 0,
+            meta: WindowMeta::default(),
+        }
+    }
+
+    #[test]
+    fn test_layout_events() {
+        let before = records(&[
+            window("a", 0, 100),
+            window("b", 0, 100),
+            window("c", 0, 100),
+        ]);
+        let now = [
+            window("a", 0, 100),
+            window("b", 50, 200),
+            window("d", 0, 100),
+        ];
+        let mut events = WindowEvents::from_layout(&before, &now);
+        events.content_changed("a");
+        events.content_changed("d");
+
+        assert_eq!(events.opened, ["d"]);
+        assert_eq!(
+            events.closed,
+            [ClosedWindow {
+                id: "c".to_string(),
+                title: "title of c".to_string()
+            }]
+        );
+        assert_eq!(events.moved, ["b"]);
+        assert_eq!(events.resized, ["b"]);
+        assert_eq!(events.content_changed, ["a"]);
+    }
+
+    #[test]
+    fn test_quiet_events_are_omitted() {
+        let json = serde_json::to_value(WindowEvents::default()).unwrap();
+        assert_eq!(json, serde_json::json!({}));
+    }
+}
@@ -0,0 +1,227 @@
+//! Scripted desktop and OCR doubles so `inspect` can be tested end to end
+//! without Xvfb or tesseract.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use image::{Rgba, RgbaImage};
+
+use crate::backend::{clip_to_screen, Capture, CaptureError, DesktopBackend};
+use crate::ocr::{OcrEngine, OcrSettings};
+use crate::{Geometry, ListedWindow, WindowMeta};
+
+/// A desktop serving a canned window list and per-window images.
+/// Windows without an image fail to capture, with the error in `failures`
+/// if there is one.
+#[derive(Default)]
+pub struct FakeDesktop {
+    pub size: [u32; 2],
+    pub focused: String,
+    pub windows: Vec<ListedWindow>,
+    pub popups: Vec<ListedWindow>,
+    /// Bottom-to-top order of visible windows; defaults to `windows` then
+    /// `popups` when empty.
+    pub stacking: Vec<String>,
+    pub images: HashMap<String, RgbaImage>,
+    pub failures: HashMap<String, CaptureError>,
+    /// Ids of windows captured so far, in order.
+    pub captures: Vec<String>,
+}
+
+impl FakeDesktop {
+    pub fn new(size: [u32; 2]) -> Self {
+        Self {
+            size,
+            ..Self::default()
+        }
+    }
+
+    /// Add a window whose capture is a solid `color` fill.
+    pub fn window(mut self, id: &str, title: &str, geometry: Geometry, color: [u8; 3]) -> Self {
+        self.images
+            .insert(id.to_string(), solid(geometry.w, geometry.h, color));
+        self.windows.push(ListedWindow {
+            id: id.to_string(),
+            geometry,
+            title: title.to_string(),
+            desktop: 0,
+            meta: WindowMeta::default(),
+        });
+        self
+    }
+
+    /// Add an override-redirect popup whose capture is a solid `color` fill.
+    pub fn popup(mut self, id: &str, geometry: Geometry, color: [u8; 3]) -> Self {
+        self.images
+            .insert(id.to_string(), solid(geometry.w, geometry.h, color));
+        self.popups.push(ListedWindow {
+            id: id.to_string(),
+            geometry,
+            title: String::new(),
+            desktop: -1,
+            meta: WindowMeta::default(),
+        });
+        self
+    }
+
+    /// Replace a window's capture, e.g. to simulate a repaint.
+    pub fn paint(&mut self, id: &str, image: RgbaImage) {
+        self.images.insert(id.to_string(), image);
+    }
+}
+
+impl DesktopBackend for FakeDesktop {
+    fn desktop_size(&mut self) -> [u32; 2] {
+        self.size
+    }
+
+    fn focused_window(&mut self) -> String {
+        self.focused.clone()
+    }
+
+    fn window_list(&mut self) -> Vec<ListedWindow> {
+        self.windows.clone()
+    }
+
+    fn popup_list(&mut self) -> Vec<ListedWindow> {
+        self.popups.clone()
+    }
+
+    fn stacking_order(&mut self) -> Vec<String> {
+        if !self.stacking.is_empty() {
+            return self.stacking.clone();
+        }
+        self.windows
+            .iter()
+            .chain(&self.popups)
+            .map(|w| w.id.clone())
+            .collect()
+    }
+
+    /// Paints every stacked window's image at its position, bottom to top.
+    fn capture_screen(&mut self) -> Result<RgbaImage, CaptureError> {
+        let mut screen = solid(self.size[0], self.size[1], [0, 0, 0]);
+        for id in self.stacking_order() {
+            let window = self.windows.iter().chain(&self.popups).find(|w| w.id == id);
+            if let (Some(window), Some(image)) = (window, self.images.get(&id)) {
+                let g = window.geometry;
+                image::imageops::overlay(&mut screen, image, i64::from(g.x), i64::from(g.y));
+            }
+        }
+        Ok(screen)
+    }
+
+    /// Like the real backends, only the on-screen part of a window that
+    /// hangs off the left or top edge is returned.
+    fn capture_window(&mut self, window: &ListedWindow) -> Result<Capture, CaptureError> {
+        self.captures.push(window.id.clone());
+        let Some(image) = self.images.get(&window.id) else {
+            return Err(self
+                .failures
+                .get(&window.id)
+                .cloned()
+                .unwrap_or_else(|| CaptureError::Failed("no canned image".to_string())));
+        };
+        let g = window.geometry;
+        let (left, top, width, height) = clip_to_screen(&g, self.size[0], self.size[1])
+            .ok_or_else(|| CaptureError::Offscreen("outside the screen".to_string()))?;
+        let inset = |screen: u32, window: i32| (i64::from(screen) - i64::from(window)) as u32;
+        let visible =
+            image::imageops::crop_imm(image, inset(left, g.x), inset(top, g.y), width, height);
+        Ok(Capture::clipped(visible.to_image(), &g, left, top))
+    }
+}
+
+/// OCR serving canned TSV keyed by window id (the capture's file stem),
+/// recording which windows were OCR'd and with what settings.
+#[derive(Default)]
+pub struct FakeOcr {
+    pub tsv: HashMap<String, String>,
+    pub calls: Mutex<Vec<String>>,
+    pub settings: Mutex<HashMap<String, OcrSettings>>,
+}
+
+impl FakeOcr {
+    pub fn with(mut self, id: &str, tsv: &str) -> Self {
+        self.tsv.insert(id.to_string(), tsv.to_string());
+        self
+    }
+
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// The settings `id` was last OCR'd with.
+    pub fn settings(&self, id: &str) -> Option<OcrSettings> {
+        self.settings.lock().unwrap().get(id).cloned()
+    }
+}
+
+impl OcrEngine for FakeOcr {
+    fn tsv(&self, image_path: &str, settings: &OcrSettings) -> Result<String> {
+        let id = Path::new(image_path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default()
+            .to_string();
+        self.calls.lock().unwrap().push(id.clone());
+        self.settings
+            .lock()
+            .unwrap()
+            .insert(id.clone(), settings.clone());
+        self.tsv
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| anyhow!("no canned TSV for {id}"))
+    }
+}
+
+/// A terminal and, focused, a dialog beside it, both flat.
+pub fn two_window_desktop() -> FakeDesktop {
+    let mut desktop = FakeDesktop::new([1280, 1024])
+        .window(
+            "0x00000001",
+            "Terminal",
+            Geometry {
+                x: 0,
+                y: 0,
+                w: 640,
+                h: 480,
+            },
+            [0, 0, 0],
+        )
+        .window(
+            "0x00000002",
+            "Save?",
+            Geometry {
+                x: 700,
+                y: 300,
+                w: 400,
+                h: 200,
+            },
+            [255, 255, 255],
+        );
+    desktop.focused = "0x00000002".to_string();
+    desktop
+}
+
+/// OCR reading the fixture text of both `two_window_desktop` windows.
+pub fn fixture_ocr() -> FakeOcr {
+    FakeOcr::default()
+        .with("0x00000001", include_str!("../fixtures/terminal.tsv"))
+        .with("0x00000002", include_str!("../fixtures/dialog.tsv"))
+}
+
+pub fn solid(width: u32, height: u32, [r, g, b]: [u8; 3]) -> RgbaImage {
+    RgbaImage::from_pixel(width, height, Rgba([r, g, b, 255]))
+}
+
+/// Fresh per-test scratch directory under the system temp dir.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lg-inspect-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,163 @@
+//! Which windows an inspection looks at.
+//!
+//! Design decision: filtering the JSON afterwards would still pay for every
+//! capture and OCR, so the filter is applied to the window list before
+//! anything is captured. A window left out keeps its hash and OCR in the
+//! state file as they were, so including it again later compares against
+//! its last real inspection rather than reporting it as new or changed.
+//! Layout events (opened, closed, ...) still cover every window: they cost
+//! nothing to compute, and a dialog opening elsewhere is exactly what an
+//! agent focused on one application needs to hear about.
+//!
+//! Popups have no title or class of their own, so `--window` and
+//! `--focused-only` keep them all (a menu usually belongs to the window the
+//! agent is working in); `--exclude` and `--region` apply to them as usual.
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+
+use crate::backend::format_window_id;
+use crate::{Geometry, ListedWindow};
+
+/// A `--window`/`--exclude` value: a window id, or a regex searched in the
+/// title, `WM_CLASS` class and instance.
+pub enum WindowPattern {
+    Id(String),
+    Text(Regex),
+}
+
+impl WindowPattern {
+    pub fn parse(value: &str) -> Result<Self> {
+        if let Some(hex) = value.strip_prefix("0x") {
+            if let Ok(id) = u32::from_str_radix(hex, 16) {
+                return Ok(Self::Id(format_window_id(id)));
+            }
+        }
+        Regex::new(value)
+            .map(Self::Text)
+            .with_context(|| format!("invalid window pattern: {value}"))
+    }
+
+    pub fn matches(&self, window: &ListedWindow) -> bool {
+        match self {
+            Self::Id(id) => window.id == *id,
+            Self::Text(regex) => [&window.title, &window.meta.class, &window.meta.instance]
+                .iter()
+                .any(|text| !text.is_empty() && regex.is_match(text)),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct WindowFilter {
+    /// Keep only windows matching one of these (all when empty).
+    pub include: Vec<WindowPattern>,
+    pub exclude: Vec<WindowPattern>,
+    pub focused_only: bool,
+    /// Keep only windows overlapping this desktop rectangle.
+    pub region: Option<Geometry>,
+}
+
+impl WindowFilter {
+    /// Whether a managed window is inspected, given the focused window id.
+    pub fn selects(&self, window: &ListedWindow, focused: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(window)))
+            && (!self.focused_only || window.id == focused)
+            && self.selects_popup(window)
+    }
+
+    /// Whether a popup is inspected; see the module docs.
+    pub fn selects_popup(&self, window: &ListedWindow) -> bool {
+        !self.exclude.iter().any(|p| p.matches(window))
+            && self
+                .region
+                .is_none_or(|region| overlaps(&window.geometry, &region))
+    }
+}
+
+/// Parse `--region x,y,w,h`.
+pub fn parse_region(value: &str) -> Result<Geometry> {
+    let parts: Vec<&str> = value.split(',').map(str::trim).collect();
+    let [x, y, w, h] = parts.as_slice() else {
+        bail!("invalid --region: {value} (x,y,w,h)");
+    };
+    let invalid = || format!("invalid --region: {value} (x,y,w,h)");
+    Ok(Geometry {
+        x: x.parse().with_context(invalid)?,
+        y: y.parse().with_context(invalid)?,
+        w: w.parse().with_context(invalid)?,
+        h: h.parse().with_context(invalid)?,
+    })
+}
+
+fn overlaps(a: &Geometry, b: &Geometry) -> bool {
+    let span = |start: i32, len: u32| (i64::from(start), i64::from(start) + i64::from(len));
+    let (ax0, ax1) = span(a.x, a.w);
+    let (ay0, ay1) = span(a.y, a.h);
+    let (bx0, bx1) = span(b.x, b.w);
+    let (by0, by1) = span(b.y, b.h);
+    ax0 < bx1 && bx0 < ax1 && ay0 < by1 && by0 < ay1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WindowMeta;
+
+    fn window(id: &str, title: &str, class: &str, x: i32) -> ListedWindow {
+        ListedWindow {
+            id: id.to_string(),
+            geometry: Geometry {
+                x,
+                y: 0,
+                w: 100,
+                h: 100,
+            },
+            title: title.to_string(),
+            desktop: 0,
+            meta: WindowMeta {
+                class: class.to_string(),
+                ..WindowMeta::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_window_patterns() {
+        let firefox = window("0x01a00003", "Mozilla Firefox", "firefox", 0);
+        let by_id = WindowPattern::parse("0x1a00003").unwrap();
+        assert!(by_id.matches(&firefox));
+        assert!(WindowPattern::parse("Fire").unwrap().matches(&firefox));
+        assert!(WindowPattern::parse("^firefox$").unwrap().matches(&firefox));
+        assert!(!WindowPattern::parse("^Terminal").unwrap().matches(&firefox));
+        assert!(WindowPattern::parse("(").is_err());
+    }
+
+    #[test]
+    fn test_filter_combines_options() {
+        let editor = window("0x00000001", "notes.txt - gedit", "Gedit", 0);
+        let terminal = window("0x00000002", "Terminal", "XTerm", 500);
+        let filter = WindowFilter {
+            include: vec![WindowPattern::parse("gedit|Terminal").unwrap()],
+            exclude: vec![WindowPattern::parse("XTerm").unwrap()],
+            ..WindowFilter::default()
+        };
+        assert!(filter.selects(&editor, ""));
+        assert!(!filter.selects(&terminal, ""));
+
+        let region = WindowFilter {
+            region: Some(parse_region("450,0,100,20").unwrap()),
+            ..WindowFilter::default()
+        };
+        assert!(!region.selects(&editor, ""));
+        assert!(region.selects(&terminal, ""));
+
+        let focused = WindowFilter {
+            focused_only: true,
+            ..WindowFilter::default()
+        };
+        assert!(focused.selects(&terminal, "0x00000002"));
+        assert!(!focused.selects(&editor, "0x00000002"));
+        assert!(parse_region("1,2,3").is_err());
+    }
+}
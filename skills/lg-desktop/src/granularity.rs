@@ -0,0 +1,248 @@
+//! `--granularity`: report phrases, lines or blocks alongside single words.
+//!
+//! Design decision: a button label like "Save As…" or a menu item like "Open
+//! Recent" is several OCR words, and an agent clicking one of them had to
+//! guess which. The grouped elements are added after the words, which stay,
+//! so one output serves both a word search and a click on a whole label;
+//! only grouped elements have ids. Grouping happens when the output is
+//! built, from the word elements and the (block, paragraph, line) tesseract
+//! put each word in, so the state file and the OCR cache keep storing words
+//! and one OCR serves every granularity. A phrase is a run of words on one line with no gap
+//! wider than 0.8 line heights between them: a space is about a third of
+//! that, while neighbouring buttons, menu items and table columns sit
+//! further apart.
+//!
+//! Results without tesseract's layout (re-read band by band, or split from a
+//! `--screen` OCR) fall back to grouping words into rows by position, with
+//! each row its own block.
+
+use crate::ocr::{self, LineKey};
+use crate::TextElement;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Granularity {
+    #[default]
+    Word,
+    Phrase,
+    Line,
+    Block,
+}
+
+impl Granularity {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "word" => Some(Self::Word),
+            "phrase" => Some(Self::Phrase),
+            "line" => Some(Self::Line),
+            "block" => Some(Self::Block),
+            _ => None,
+        }
+    }
+}
+
+/// Word `elements` followed by their groups at `granularity`. `lines` holds
+/// each element's tesseract line, or is empty when unknown. Grouped elements
+/// get ids (`p0`, `l0`, `b0`, ...) numbered in reading order within the
+/// window; words have none.
+pub fn group(
+    elements: Vec<TextElement>,
+    lines: &[LineKey],
+    granularity: Granularity,
+) -> Vec<TextElement> {
+    let lines = split_lines(&elements, lines);
+    let (prefix, groups): (&str, Vec<Vec<Vec<usize>>>) = match granularity {
+        Granularity::Word => return elements,
+        Granularity::Phrase => (
+            "p",
+            lines
+                .iter()
+                .flat_map(|(_, line)| phrases(&elements, line))
+                .map(|phrase| vec![phrase])
+                .collect(),
+        ),
+        Granularity::Line => ("l", lines.into_iter().map(|(_, line)| vec![line]).collect()),
+        Granularity::Block => ("b", blocks(lines)),
+    };
+    let grouped: Vec<TextElement> = groups
+        .iter()
+        .enumerate()
+        .map(|(n, lines)| merge(&elements, lines, format!("{prefix}{n}")))
+        .collect();
+    elements.into_iter().chain(grouped).collect()
+}
+
+/// Element indices per line, left to right, with each line's block.
+fn split_lines(elements: &[TextElement], keys: &[LineKey]) -> Vec<(u32, Vec<usize>)> {
+    let mut lines: Vec<(u32, Vec<usize>)> = if keys.len() == elements.len() {
+        let mut by_key: Vec<(LineKey, Vec<usize>)> = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            match by_key.iter_mut().find(|(k, _)| k == key) {
+                Some((_, line)) => line.push(i),
+                None => by_key.push((*key, vec![i])),
+            }
+        }
+        by_key
+            .into_iter()
+            .map(|((block, _, _), line)| (block, line))
+            .collect()
+    } else {
+        (0..).zip(ocr::rows(elements)).collect()
+    };
+    for (_, line) in &mut lines {
+        line.sort_by_key(|&i| elements[i].x);
+    }
+    lines
+}
+
+/// Split a line wherever the gap between words is wider than a space.
+fn phrases(elements: &[TextElement], line: &[usize]) -> Vec<Vec<usize>> {
+    let height = line.iter().map(|&i| elements[i].h).max().unwrap_or(0);
+    let mut phrases: Vec<Vec<usize>> = Vec::new();
+    // Right edge of the phrase so far.
+    let mut end = 0;
+    for &i in line {
+        let e = &elements[i];
+        let gap = i64::from(e.x) - end;
+        match phrases.last_mut() {
+            Some(phrase) if gap * 5 <= i64::from(height) * 4 => {
+                phrase.push(i);
+                end = end.max(i64::from(e.x) + i64::from(e.w));
+            }
+            _ => {
+                phrases.push(vec![i]);
+                end = i64::from(e.x) + i64::from(e.w);
+            }
+        }
+    }
+    phrases
+}
+
+/// Consecutive lines of the same block.
+fn blocks(lines: Vec<(u32, Vec<usize>)>) -> Vec<Vec<Vec<usize>>> {
+    let mut blocks: Vec<(u32, Vec<Vec<usize>>)> = Vec::new();
+    for (block, line) in lines {
+        match blocks.last_mut() {
+            Some((last, group)) if *last == block => group.push(line),
+            _ => blocks.push((block, vec![line])),
+        }
+    }
+    blocks.into_iter().map(|(_, group)| group).collect()
+}
+
+/// One element covering `lines` of words: the union box, words joined by
+/// spaces and lines by newlines, and the lowest confidence.
+fn merge(elements: &[TextElement], lines: &[Vec<usize>], id: String) -> TextElement {
+    let words = || lines.iter().flatten().map(|&i| &elements[i]);
+    let x0 = words().map(|e| i64::from(e.x)).min().unwrap_or(0);
+    let y0 = words().map(|e| i64::from(e.y)).min().unwrap_or(0);
+    let x1 = words()
+        .map(|e| i64::from(e.x) + i64::from(e.w))
+        .max()
+        .unwrap_or(0);
+    let y1 = words()
+        .map(|e| i64::from(e.y) + i64::from(e.h))
+        .max()
+        .unwrap_or(0);
+    let text = lines
+        .iter()
+        .map(|line| {
+            line.iter()
+                .map(|&i| elements[i].text.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    TextElement {
+        id: Some(id),
+        text,
+        x: i32::try_from(x0).unwrap_or(0),
+        y: i32::try_from(y0).unwrap_or(0),
+        w: u32::try_from(x1 - x0).unwrap_or(0),
+        h: u32::try_from(y1 - y0).unwrap_or(0),
+        confidence: words().map(|e| e.confidence).fold(f32::INFINITY, f32::min),
+        visibility: crate::occlusion::Visibility::Visible,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Geometry;
+
+    fn parse(tsv: &str) -> crate::OcrResult {
+        let origin = Geometry {
+            x: 0,
+            y: 0,
+            w: 400,
+            h: 200,
+        };
+        ocr::parse_tsv(tsv, &origin, 40.0)
+    }
+
+    /// The grouped elements, after the words.
+    fn grouped(elements: Vec<TextElement>) -> Vec<TextElement> {
+        elements.into_iter().filter(|e| e.id.is_some()).collect()
+    }
+
+    fn texts(elements: &[TextElement]) -> Vec<(&str, &str)> {
+        elements
+            .iter()
+            .map(|e| (e.id.as_deref().unwrap_or(""), e.text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_group_dialog_fixture() {
+        let dialog = parse(include_str!("../fixtures/dialog.tsv"));
+        let at = |g| grouped(group(dialog.elements.clone(), &dialog.lines, g));
+
+        let words = group(dialog.elements.clone(), &dialog.lines, Granularity::Word);
+        assert_eq!(words.len(), 6);
+        // Words are kept, ahead of the groups.
+        let with_phrases = group(dialog.elements.clone(), &dialog.lines, Granularity::Phrase);
+        assert_eq!(with_phrases.len(), 6 + 3);
+        assert!(with_phrases[..6].iter().all(|e| e.id.is_none()));
+        // The buttons are further apart than a space.
+        let phrases = at(Granularity::Phrase);
+        assert_eq!(
+            texts(&phrases),
+            [
+                ("p0", "Save changes to document?"),
+                ("p1", "Cancel"),
+                ("p2", "Save")
+            ]
+        );
+        let save = &phrases[2];
+        assert_eq!((save.x, save.y, save.w, save.h), (300, 150, 40, 16));
+        assert_eq!(
+            texts(&at(Granularity::Line)),
+            [("l0", "Save changes to document?"), ("l1", "Cancel Save")]
+        );
+        let question = &at(Granularity::Line)[0];
+        assert_eq!((question.x, question.w), (20, 238));
+        assert!((question.confidence - 91.3).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_group_blocks_and_fallback_rows() {
+        let terminal = parse(include_str!("../fixtures/terminal.tsv"));
+        let blocks = grouped(group(
+            terminal.elements.clone(),
+            &terminal.lines,
+            Granularity::Block,
+        ));
+        assert_eq!(
+            texts(&blocks),
+            [("b0", "user@host:~$ ls\nDocuments Downloads")]
+        );
+        assert_eq!((blocks[0].w, blocks[0].h), (166, 32));
+
+        // Without tesseract's layout, each row is a block of its own.
+        let rows = grouped(group(terminal.elements, &[], Granularity::Block));
+        assert_eq!(
+            texts(&rows),
+            [("b0", "user@host:~$ ls"), ("b1", "Documents Downloads")]
+        );
+    }
+}
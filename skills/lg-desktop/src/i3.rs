@@ -0,0 +1,286 @@
+//! i3 window manager state and commands over i3's IPC socket.
+//!
+//! Design decision: EWMH tells us which windows exist, but not how i3 has
+//! arranged them: a tabbed or stacked container shows one window and hides
+//! its siblings behind tabs, which looks to an agent like windows that never
+//! render. i3's IPC protocol reports workspaces and the container tree
+//! directly, and accepts the same commands as the config file, so an agent
+//! can switch workspace, float a window or split a tabbed container to get
+//! every window on screen before OCR. The protocol is a small framed JSON
+//! exchange on a Unix socket, so we speak it directly instead of pulling in
+//! an i3 client crate.
+//!
+//! The tree is reported per workspace and compacted: only split layouts,
+//! window ids and the focused/urgent flags are kept, which is what matters
+//! for reading the screen; geometry is already in `windows[]`.
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::process::Command;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::format_window_id;
+use crate::is_false;
+
+const MAGIC: &[u8] = b"i3-ipc";
+const RUN_COMMAND: u32 = 0;
+const GET_WORKSPACES: u32 = 1;
+const GET_TREE: u32 = 4;
+
+/// i3 answers in milliseconds; anything slower means it is wedged.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Workspaces and their layout, as reported under `i3` in the output.
+#[derive(Serialize, Deserialize)]
+pub struct I3State {
+    pub workspaces: Vec<Workspace>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Workspace {
+    pub num: i64,
+    pub name: String,
+    /// Shown on its output right now.
+    pub visible: bool,
+    pub focused: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub urgent: bool,
+    /// Tiled containers, with the workspace's own layout.
+    pub tiling: Container,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub floating: Vec<Container>,
+}
+
+/// A split container (`layout` and `children`) or a window (`window`).
+#[derive(Serialize, Deserialize, Default)]
+pub struct Container {
+    /// `splith`, `splitv`, `tabbed` or `stacked`; absent for windows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
+    /// Window id as in `windows[]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window: Option<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub focused: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub urgent: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Container>,
+}
+
+/// A node of i3's `GET_TREE` reply, keeping the fields we report.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Node {
+    #[serde(rename = "type")]
+    kind: String,
+    name: Option<String>,
+    layout: String,
+    window: Option<u32>,
+    focused: bool,
+    urgent: bool,
+    nodes: Vec<Node>,
+    floating_nodes: Vec<Node>,
+}
+
+/// An entry of i3's `GET_WORKSPACES` reply.
+#[derive(Deserialize)]
+struct WorkspaceReply {
+    num: i64,
+    name: String,
+    visible: bool,
+    focused: bool,
+    urgent: bool,
+}
+
+#[derive(Deserialize)]
+struct CommandReply {
+    success: bool,
+    error: Option<String>,
+}
+
+pub struct I3Ipc {
+    stream: UnixStream,
+}
+
+impl I3Ipc {
+    /// Connect to the running i3, finding its socket via `$I3SOCK` or
+    /// `i3 --get-socketpath`.
+    pub fn connect() -> Result<Self> {
+        let path = match std::env::var("I3SOCK") {
+            Ok(path) if !path.is_empty() => path,
+            _ => socket_path()?,
+        };
+        let stream = UnixStream::connect(&path)
+            .with_context(|| format!("failed to connect to i3 at {path}"))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        Ok(Self { stream })
+    }
+
+    fn request(&mut self, kind: u32, payload: &str) -> Result<Vec<u8>> {
+        self.stream.write_all(&encode(kind, payload.as_bytes()))?;
+        let (reply_kind, body) = read_message(&mut self.stream)?;
+        if reply_kind != kind {
+            bail!("i3 replied with message type {reply_kind} to a type {kind} request");
+        }
+        Ok(body)
+    }
+
+    /// Workspaces with their compacted container trees.
+    pub fn state(&mut self) -> Result<I3State> {
+        let workspaces: Vec<WorkspaceReply> =
+            serde_json::from_slice(&self.request(GET_WORKSPACES, "")?)
+                .context("malformed i3 workspace list")?;
+        let tree: Node =
+            serde_json::from_slice(&self.request(GET_TREE, "")?).context("malformed i3 tree")?;
+        Ok(combine(workspaces, &tree))
+    }
+
+    /// Run an i3 command such as `workspace 2` or
+    /// `[id=0x01a00003] floating toggle`, failing if i3 rejects it.
+    pub fn command(&mut self, command: &str) -> Result<()> {
+        let replies: Vec<CommandReply> =
+            serde_json::from_slice(&self.request(RUN_COMMAND, command)?)
+                .context("malformed i3 command reply")?;
+        for reply in replies {
+            if !reply.success {
+                bail!(
+                    "i3 rejected `{command}`: {}",
+                    reply.error.unwrap_or_default()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn socket_path() -> Result<String> {
+    let out = Command::new("i3")
+        .arg("--get-socketpath")
+        .output()
+        .context("failed to run i3 --get-socketpath")?;
+    if !out.status.success() {
+        bail!(
+            "i3 --get-socketpath failed (exit {}); is i3 running?",
+            out.status
+        );
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// Frame a message: magic, payload length and type (native endian), payload.
+fn encode(kind: u32, payload: &[u8]) -> Vec<u8> {
+    let mut message = MAGIC.to_vec();
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(payload);
+    message
+}
+
+/// Read one framed message, returning its type and payload.
+fn read_message(reader: &mut impl Read) -> Result<(u32, Vec<u8>)> {
+    let mut header = [0u8; 14];
+    reader
+        .read_exact(&mut header)
+        .context("failed to read i3 reply")?;
+    if &header[..6] != MAGIC {
+        bail!("not an i3 IPC reply");
+    }
+    let len = u32::from_ne_bytes(header[6..10].try_into()?);
+    let kind = u32::from_ne_bytes(header[10..14].try_into()?);
+    let mut body = vec![0; len as usize];
+    reader.read_exact(&mut body).context("truncated i3 reply")?;
+    Ok((kind, body))
+}
+
+/// Pair each workspace with its node in the tree.
+fn combine(workspaces: Vec<WorkspaceReply>, tree: &Node) -> I3State {
+    let mut nodes = Vec::new();
+    collect_workspaces(tree, &mut nodes);
+    let workspaces = workspaces
+        .into_iter()
+        .map(|ws| {
+            let node = nodes
+                .iter()
+                .find(|n| n.name.as_deref() == Some(ws.name.as_str()));
+            Workspace {
+                tiling: node.map(|n| compact(n)).unwrap_or_default(),
+                floating: node
+                    .map(|n| n.floating_nodes.iter().map(compact).collect())
+                    .unwrap_or_default(),
+                num: ws.num,
+                name: ws.name,
+                visible: ws.visible,
+                focused: ws.focused,
+                urgent: ws.urgent,
+            }
+        })
+        .collect();
+    I3State { workspaces }
+}
+
+fn collect_workspaces<'a>(node: &'a Node, found: &mut Vec<&'a Node>) {
+    if node.kind == "workspace" {
+        found.push(node);
+        return;
+    }
+    for child in &node.nodes {
+        collect_workspaces(child, found);
+    }
+}
+
+fn compact(node: &Node) -> Container {
+    // i3 wraps every floating window in a `floating_con` holding just it.
+    if let [only] = node.nodes.as_slice() {
+        if node.kind == "floating_con" {
+            return compact(only);
+        }
+    }
+    let window = node.window.map(format_window_id);
+    Container {
+        layout: window.is_none().then(|| node.layout.clone()),
+        window,
+        focused: node.focused,
+        urgent: node.urgent,
+        children: node.nodes.iter().map(compact).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_framing_round_trip() {
+        let message = encode(GET_TREE, b"{}");
+        assert_eq!(&message[..6], b"i3-ipc");
+        assert_eq!(message.len(), 14 + 2);
+        let (kind, body) = read_message(&mut message.as_slice()).unwrap();
+        assert_eq!((kind, body.as_slice()), (GET_TREE, &b"{}"[..]));
+        assert!(read_message(&mut &b"X11-ipc\0\0\0\0\0\0\0"[..]).is_err());
+    }
+
+    #[test]
+    fn test_state_from_fixture() {
+        let workspaces: Vec<WorkspaceReply> =
+            serde_json::from_str(include_str!("../fixtures/i3-workspaces.json")).unwrap();
+        let tree: Node = serde_json::from_str(include_str!("../fixtures/i3-tree.json")).unwrap();
+        let state = combine(workspaces, &tree);
+
+        assert_eq!(state.workspaces.len(), 2);
+        let first = &state.workspaces[0];
+        assert!(first.visible && first.focused);
+        assert_eq!(first.tiling.layout.as_deref(), Some("splith"));
+        let tabs = &first.tiling.children[1];
+        assert_eq!(tabs.layout.as_deref(), Some("tabbed"));
+        assert_eq!(tabs.children[0].window.as_deref(), Some("0x01a00003"));
+        assert!(tabs.children[1].focused);
+        // The floating dialog is unwrapped from its floating_con.
+        assert_eq!(first.floating[0].window.as_deref(), Some("0x01e00001"));
+        assert!(state.workspaces[1].urgent && !state.workspaces[1].visible);
+    }
+}
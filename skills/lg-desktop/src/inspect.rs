@@ -1,11 +1,36 @@
-use std::collections::HashMap;
+mod backend;
+mod cache;
+mod change;
+mod config;
+mod daemon;
+mod diff;
+mod events;
+#[cfg(test)]
+mod fake;
+mod filter;
+mod granularity;
+mod i3;
+mod layout;
+mod occlusion;
+mod ocr;
+mod preprocess;
+mod screen;
+mod tiles;
+
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::process::Command;
-use std::time::SystemTime;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use image::RgbaImage;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+
+use backend::{BackendChoice, Capture, CaptureError, DesktopBackend};
+use change::{ChangeConfig, ChangeMode, IgnoreRegion};
+use ocr::{OcrChoice, OcrEngine, OcrSettings, Reader};
 
 const STATE_PATH: &str = "/shared/lg-state.json";
 const SCREENSHOT_DIR: &str = "/shared/screenshots";
@@ -15,8 +40,30 @@ struct InspectOutput {
     timestamp: String,
     desktop_size: [u32; 2],
     focused_window: String,
+    /// The OCR settings windows are read with, unless they say otherwise.
+    #[serde(default)]
+    ocr_settings: OcrSettings,
     windows: Vec<WindowInfo>,
+    /// Mapped override-redirect windows (menus, dropdowns, tooltips), bottom
+    /// to top, in the same shape as `windows`. Not part of the events below.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    popups: Vec<WindowInfo>,
+    /// New or content-changed windows (kept for older callers; see the
+    /// event lists below for the breakdown).
     changes_since_last: Vec<String>,
+    #[serde(flatten)]
+    events: events::WindowEvents,
+    /// With `--screen`: text outside every window (bars, desktop), in
+    /// absolute coordinates.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    unattributed: Vec<TextElement>,
+    /// With `--i3`: workspaces and how i3 has laid out their windows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    i3: Option<i3::I3State>,
+    /// Windows the filter left out, so not captured: the daemon keeps their
+    /// damage queued for the next query.
+    #[serde(skip)]
+    left_out: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -24,13 +71,82 @@ struct WindowInfo {
     id: String,
     title: String,
     geometry: Geometry,
+    /// Workspace index; absent for popups and windows on every workspace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    desktop: Option<i64>,
+    #[serde(flatten)]
+    meta: WindowMeta,
     ocr_text: String,
     /// Clickable text elements with absolute desktop coordinates.
-    /// Only populated for changed windows (when OCR runs).
     /// To click an element: use center point (x + w/2, y + h/2).
     #[serde(skip_serializing_if = "Vec::is_empty")]
     elements: Vec<TextElement>,
     changed: bool,
+    status: WindowStatus,
+    /// Why `status` is not `ok`/`unchanged`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    /// `ocr_text`/`elements` come from the OCR of an earlier, pixel-identical
+    /// capture rather than a fresh tesseract run.
+    #[serde(default, skip_serializing_if = "is_false")]
+    cached: bool,
+    /// Set when a config file rule gives the window other OCR settings than
+    /// the top-level `ocr_settings`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ocr_settings: Option<OcrSettings>,
+    /// `ocr_text`/`elements` were read from a cleaned-up capture (see
+    /// `preprocess`), because the capture as is gave almost no text or the
+    /// window's settings ask for it.
+    #[serde(default, skip_serializing_if = "is_false")]
+    preprocessed: bool,
+    /// With `--diff`: what changed since the previous inspection, replacing
+    /// `ocr_text` and `elements`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    diff: Option<diff::ElementDiff>,
+    /// Position in the stacking order, 0 at the bottom; absent when the
+    /// window is not on screen or the order is unknown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    z_index: Option<usize>,
+    /// How much of the window is covered by windows above it.
+    #[serde(default, skip_serializing_if = "occlusion::Visibility::is_visible")]
+    visibility: occlusion::Visibility,
+    /// With `--layout`: blocks, paragraphs, lines and words.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    layout: Vec<layout::Block>,
+}
+
+/// Outcome of inspecting one window.
+///
+/// Design decision: a failed capture used to be reported as `changed: true`
+/// with empty text, which looked exactly like a window that went blank and
+/// sent agents chasing phantom changes. Failures now leave `changed` false
+/// and say what happened, so an agent knows to escalate to a screenshot or
+/// restore the window instead.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+enum WindowStatus {
+    /// Captured and OCR'd (or served from cache after a change check).
+    #[default]
+    Ok,
+    /// Captured (or known clean) and identical to the previous inspection.
+    Unchanged,
+    CaptureFailed,
+    OcrFailed,
+    /// On another workspace or outside the screen.
+    Offscreen,
+    Minimized,
+}
+
+impl WindowStatus {
+    /// Whether the window should have been read but was not.
+    fn failed(self) -> bool {
+        matches!(self, Self::CaptureFailed | Self::OcrFailed)
+    }
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)] // serde's skip_serializing_if passes &T
+fn is_false(value: &bool) -> bool {
+    !*value
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy)]
@@ -51,13 +167,18 @@ struct Geometry {
 /// text positions rather than semantic widget types, but for click targeting this
 /// is sufficient -- the AI can click on any visible text element by its coordinates.
 ///
-/// Coordinates are absolute (window position + element offset within screenshot).
-/// Known limitation: window decorations may cause ~30px y-offset since scrot -u
-/// captures including title bar but wmctrl reports content area position. In practice
-/// XFCE title bars are thin and most clickable elements are well within the content
-/// area, so the offset rarely causes misclicks.
-#[derive(Serialize, Deserialize)]
+/// Coordinates are absolute: the window's content-area origin, plus where the
+/// capture sits inside the window (non-zero when it hangs off the left or top
+/// screen edge), plus the element's offset within the capture. Backends report
+/// the true content-area origin (see `backend::shell::get_client_origins` for
+/// the `wmctrl` decoration offset), so elements can be clicked as reported.
+#[derive(Serialize, Deserialize, Clone)]
 struct TextElement {
+    /// With `--granularity phrase|line|block`: `p0`, `l0`, `b0`, ... in
+    /// reading order within the window, on the grouped elements that follow
+    /// the words.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
     text: String,
     /// Absolute desktop X coordinate (top-left of bounding box)
     x: i32,
@@ -66,11 +187,128 @@ struct TextElement {
     w: u32,
     h: u32,
     confidence: f32,
+    /// Whether windows stacked above cover the element.
+    #[serde(default, skip_serializing_if = "occlusion::Visibility::is_visible")]
+    visibility: occlusion::Visibility,
+}
+
+/// A managed client window as listed by the desktop backend.
+#[derive(Clone)]
+struct ListedWindow {
+    id: String,
+    geometry: Geometry,
+    title: String,
+    /// EWMH desktop (workspace) index; -1 for sticky windows.
+    desktop: i64,
+    meta: WindowMeta,
+}
+
+/// What application a window belongs to and what role it plays, from its
+/// EWMH/ICCCM properties. Fields the window does not set are omitted.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+struct WindowMeta {
+    /// `WM_CLASS` class, e.g. `Firefox`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    class: String,
+    /// `WM_CLASS` instance, e.g. `Navigator`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    instance: String,
+    /// `_NET_WM_PID`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    /// Preferred `_NET_WM_WINDOW_TYPE`, lowercased without the prefix:
+    /// `normal`, `dialog`, `utility`, `popup_menu`, ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    window_type: Option<String>,
+    /// `_NET_WM_STATE` flags in the same form: `modal`, `fullscreen`,
+    /// `hidden`, `demands_attention`, ...
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    state: Vec<String>,
+    /// `WM_TRANSIENT_FOR`: the window a dialog belongs to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    transient_for: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Default)]
 struct PreviousState {
     windows: HashMap<String, String>, // id -> hash
+    /// Last OCR result per window and capture hash, so unchanged windows can
+    /// report their text without re-running tesseract. Keyed by window too:
+    /// in `phash` mode unrelated windows can share a hash (every flat window
+    /// hashes to zero), and must not be served each other's text.
+    #[serde(default)]
+    ocr: HashMap<String, OcrResult>, // "id:hash" -> OCR
+    /// Every listed window, for opened/closed/moved/resized events. `None`
+    /// in a fresh state, and in state files written before it was recorded.
+    #[serde(default)]
+    layout: Option<HashMap<String, events::WindowRecord>>, // id -> title, geometry
+    /// Hash of the last `--screen` capture; its OCR is in `ocr`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    screen: Option<String>,
+}
+
+impl PreviousState {
+    /// The OCR of window `id`'s capture with `hash`.
+    fn ocr_of(&self, id: &str, hash: &str) -> Option<&OcrResult> {
+        self.ocr.get(&format!("{id}:{hash}"))
+    }
+
+    fn remember_ocr(&mut self, id: &str, hash: &str, result: OcrResult) {
+        self.ocr.insert(format!("{id}:{hash}"), result);
+    }
+}
+
+/// OCR output for one capture, with element coordinates relative to the
+/// window's top-left corner so the entry survives the window moving.
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct OcrResult {
+    text: String,
+    elements: Vec<TextElement>,
+    /// Band hashes of the capture this was read from; see `tiles`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tiles: Vec<String>,
+    /// Tesseract's (block, paragraph, line) of each element, in step with
+    /// `elements`; empty when the result was pieced together from several
+    /// OCR runs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    lines: Vec<ocr::LineKey>,
+    /// `OcrSettings::fingerprint` of the settings this was read with, so a
+    /// result is not reused once the settings change.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    settings: String,
+    /// Read from the preprocessed capture; see `preprocess`.
+    #[serde(default, skip_serializing_if = "is_false")]
+    preprocessed: bool,
+}
+
+impl OcrResult {
+    /// The same result with every element moved by `(dx, dy)`.
+    fn shifted(&self, dx: i32, dy: i32) -> OcrResult {
+        OcrResult {
+            elements: self
+                .elements
+                .iter()
+                .map(|e| TextElement {
+                    x: e.x + dx,
+                    y: e.y + dy,
+                    ..e.clone()
+                })
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Elements shifted to absolute desktop coordinates.
+    fn absolute_elements(&self, origin: &Geometry) -> Vec<TextElement> {
+        self.elements
+            .iter()
+            .map(|e| TextElement {
+                x: e.x + origin.x,
+                y: e.y + origin.y,
+                ..e.clone()
+            })
+            .collect()
+    }
 }
 
 fn get_timestamp() -> String {
@@ -121,343 +359,896 @@ fn is_leap_year(y: u64) -> bool {
     (y % 4 == 0 && y % 100 != 0) || y % 400 == 0
 }
 
-fn get_desktop_size() -> [u32; 2] {
-    let output = Command::new("xprop")
-        .args(["-root", "_NET_DESKTOP_GEOMETRY"])
-        .output();
-
-    match output {
-        Ok(out) if out.status.success() => {
-            let text = String::from_utf8_lossy(&out.stdout);
-            // Format: "_NET_DESKTOP_GEOMETRY(CARDINAL) = 1920, 1080"
-            if let Some(eq_pos) = text.find('=') {
-                let values: Vec<u32> = text[eq_pos + 1..]
-                    .split(',')
-                    .filter_map(|s| s.trim().parse().ok())
-                    .collect();
-                if values.len() == 2 {
-                    return [values[0], values[1]];
-                }
-            }
-            eprintln!("[lg-inspect] warning: could not parse desktop size from xprop output");
-            [0, 0]
-        }
-        Ok(out) => {
-            eprintln!(
-                "[lg-inspect] warning: xprop failed (exit {}): {}",
-                out.status,
-                String::from_utf8_lossy(&out.stderr).trim()
-            );
-            [0, 0]
-        }
+/// Save a window capture as PNG for hashing and OCR.
+fn save_capture(image: &RgbaImage, dir: &Path, window_id: &str) -> Option<String> {
+    let path = dir.join(format!("{window_id}.png"));
+    match image.save(&path) {
+        Ok(()) => Some(path.to_string_lossy().into_owned()),
         Err(e) => {
-            eprintln!("[lg-inspect] warning: failed to run xprop: {e}");
-            [0, 0]
+            eprintln!("[lg-inspect] warning: failed to save capture for window {window_id}: {e}");
+            None
         }
     }
 }
 
-fn get_focused_window() -> String {
-    let output = Command::new("xdotool").arg("getactivewindow").output();
-
-    match output {
-        Ok(out) if out.status.success() => String::from_utf8_lossy(&out.stdout).trim().to_string(),
-        Ok(out) => {
-            eprintln!(
-                "[lg-inspect] warning: xdotool getactivewindow failed (exit {})",
-                out.status
-            );
-            String::new()
-        }
+fn load_previous_state() -> PreviousState {
+    match fs::read_to_string(STATE_PATH) {
+        Ok(content) => match serde_json::from_str(&content) {
+            Ok(state) => state,
+            Err(e) => {
+                eprintln!("[lg-inspect] warning: corrupt state file {STATE_PATH}, resetting: {e}");
+                PreviousState::default()
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => PreviousState::default(),
         Err(e) => {
-            eprintln!("[lg-inspect] warning: failed to run xdotool: {e}");
-            String::new()
+            eprintln!("[lg-inspect] warning: failed to read state file {STATE_PATH}: {e}");
+            PreviousState::default()
         }
     }
 }
 
-fn get_window_list() -> Vec<(String, Geometry, String)> {
-    let output = Command::new("wmctrl").args(["-lG"]).output();
+/// When the state file was last written, if it exists.
+fn state_modified() -> Option<SystemTime> {
+    fs::metadata(STATE_PATH).and_then(|m| m.modified()).ok()
+}
 
-    let out = match output {
-        Ok(out) if out.status.success() => out,
-        Ok(out) => {
-            eprintln!(
-                "[lg-inspect] warning: wmctrl failed (exit {}): {}",
-                out.status,
-                String::from_utf8_lossy(&out.stderr).trim()
-            );
-            return Vec::new();
-        }
-        Err(e) => {
-            eprintln!("[lg-inspect] warning: failed to run wmctrl: {e}");
-            return Vec::new();
-        }
-    };
+fn save_state(state: &PreviousState) -> Result<()> {
+    if let Some(parent) = Path::new(STATE_PATH).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let json = serde_json::to_string_pretty(state)?;
+    let tmp_path = format!("{STATE_PATH}.tmp");
+    fs::write(&tmp_path, &json).context("failed to write temp state")?;
+    fs::rename(&tmp_path, STATE_PATH).context("failed to rename temp state to final")?;
+    Ok(())
+}
 
-    let text = String::from_utf8_lossy(&out.stdout);
-    let mut windows = Vec::new();
-
-    for line in text.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        // wmctrl -lG format: ID DESKTOP X Y W H HOSTNAME TITLE...
-        if parts.len() >= 8 {
-            let id = parts[0].to_string();
-            let x = parts[2].parse().unwrap_or(0);
-            let y = parts[3].parse().unwrap_or(0);
-            let w = parts[4].parse().unwrap_or(0);
-            let h = parts[5].parse().unwrap_or(0);
-            let title = parts[7..].join(" ");
-            let geom = Geometry { x, y, w, h };
-            windows.push((id, geom, title));
-        }
+#[derive(Default)]
+#[allow(clippy::struct_excessive_bools)] // independent command-line flags
+struct Options {
+    /// Only include changed windows in output (reduces token overhead)
+    changes_only: bool,
+    /// Report changed windows as an element/line diff (implies changes_only).
+    diff: bool,
+    backend: BackendChoice,
+    ocr_engine: OcrChoice,
+    /// Languages, confidence cutoff and tesseract modes, globally and per
+    /// window.
+    ocr: config::OcrConfig,
+    change: ChangeConfig,
+    /// Run as the long-lived inspect daemon instead of inspecting once.
+    daemon: bool,
+    /// Inspect in-process even if a daemon is listening.
+    no_daemon: bool,
+    /// OCR the whole screen once and attribute text to windows.
+    screen: bool,
+    /// Report i3 workspaces and layout.
+    i3: bool,
+    /// Report each window's block/paragraph/line tree.
+    layout: bool,
+    /// i3 commands to run before inspecting, in order.
+    i3_commands: Vec<String>,
+    /// Which windows to capture at all.
+    filter: filter::WindowFilter,
+    /// What each of `elements` is: a word, phrase, line or block.
+    granularity: granularity::Granularity,
+    /// Windows OCR'd at once; `None` picks from the CPU count.
+    jobs: Option<usize>,
+    /// OCR results of earlier captures, by pixel hash.
+    ocr_cache: cache::OcrCache,
+}
+
+impl Options {
+    /// Most machines run out of cores before windows, and tesseract itself
+    /// uses a few threads per image, so the default stays small.
+    fn jobs(&self) -> usize {
+        self.jobs.unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map_or(1, std::num::NonZeroUsize::get)
+                .min(4)
+        })
     }
+}
 
-    windows
+/// `LG_INSPECT_*` variables by name. A daemon query carries the client's
+/// along with its flags, so the daemon parses both as the client would.
+type Env = HashMap<String, String>;
+
+fn client_env() -> Env {
+    std::env::vars()
+        .filter(|(name, _)| name.starts_with("LG_INSPECT_"))
+        .collect()
 }
 
-fn capture_window(window_id: &str) -> Option<String> {
-    if let Err(e) = fs::create_dir_all(SCREENSHOT_DIR) {
-        eprintln!("[lg-inspect] warning: failed to create screenshot dir: {e}");
-        return None;
-    }
-    let path = format!("{SCREENSHOT_DIR}/{window_id}.png");
-
-    // Focus the window, then capture the focused window with scrot -u
-    let focus = Command::new("xdotool")
-        .args(["windowfocus", "--sync", window_id])
-        .status();
-    if !matches!(focus, Ok(s) if s.success()) {
-        eprintln!("[lg-inspect] warning: failed to focus window {window_id}");
-        return None;
-    }
-
-    let status = Command::new("scrot")
-        .args(["-u", "-z", "-o", &path])
-        .status();
-
-    match status {
-        Ok(s) if s.success() => Some(path),
-        Ok(s) => {
-            eprintln!(
-                "[lg-inspect] warning: scrot exited with {} for window {window_id}",
-                s.code().map_or("signal".to_string(), |c| c.to_string())
-            );
-            None
+fn parse_args(args: impl IntoIterator<Item = String>, env: &Env) -> Result<Options> {
+    let mut options = Options {
+        ocr: config::OcrConfig::load(env)?,
+        ..Options::default()
+    };
+    parse_env(&mut options, env)?;
+    apply_flags(options, args)
+}
+
+/// `options` with the command-line flags on top.
+fn apply_flags(mut options: Options, args: impl IntoIterator<Item = String>) -> Result<Options> {
+    parse_flags(&mut options, args)?;
+    if options.screen && options.diff {
+        bail!("--screen cannot be combined with --diff");
+    }
+    Ok(options)
+}
+
+/// Defaults from `LG_INSPECT_*` variables, which flags override.
+fn parse_env(options: &mut Options, env: &Env) -> Result<()> {
+    if let Some(value) = env.get("LG_INSPECT_BACKEND") {
+        options.backend = BackendChoice::parse(value)
+            .with_context(|| format!("invalid LG_INSPECT_BACKEND: {value}"))?;
+    }
+    if let Some(value) = env.get("LG_INSPECT_OCR_ENGINE") {
+        options.ocr_engine = OcrChoice::parse(value)
+            .with_context(|| format!("invalid LG_INSPECT_OCR_ENGINE: {value}"))?;
+    }
+    options.ocr.apply_env(env)?;
+    if let Some(value) = env.get("LG_INSPECT_CHANGE_MODE") {
+        options.change.mode = ChangeMode::parse(value)
+            .with_context(|| format!("invalid LG_INSPECT_CHANGE_MODE: {value}"))?;
+    }
+    if let Some(value) = env.get("LG_INSPECT_OCR_CACHE_MB") {
+        let megabytes: u64 = value
+            .parse()
+            .with_context(|| format!("invalid LG_INSPECT_OCR_CACHE_MB: {value}"))?;
+        options.ocr_cache.set_max_bytes(megabytes * 1024 * 1024);
+    }
+    if let Some(value) = env.get("LG_INSPECT_JOBS") {
+        options.jobs = Some(parse_jobs(value).context("invalid LG_INSPECT_JOBS")?);
+    }
+    // Semicolon-separated, e.g. "*:-120,0,120,24;Firefox:0,0,32,32"
+    if let Some(value) = env.get("LG_INSPECT_IGNORE_REGIONS") {
+        for spec in value.split(';').filter(|s| !s.trim().is_empty()) {
+            options
+                .change
+                .ignore
+                .push(IgnoreRegion::parse(spec.trim())?);
         }
-        Err(e) => {
-            eprintln!("[lg-inspect] error: failed to execute scrot: {e}");
-            None
+    }
+    Ok(())
+}
+
+fn parse_flags(options: &mut Options, args: impl IntoIterator<Item = String>) -> Result<()> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("{arg} requires a value"))
+        };
+        match arg.as_str() {
+            "--changes-only" => options.changes_only = true,
+            "--diff" => {
+                options.diff = true;
+                options.changes_only = true;
+            }
+            "--daemon" => options.daemon = true,
+            "--no-daemon" => options.no_daemon = true,
+            "--screen" => options.screen = true,
+            "--no-ocr-cache" => options.ocr_cache = cache::OcrCache::disabled(),
+            "--i3" => options.i3 = true,
+            "--layout" => options.layout = true,
+            "--i3-command" => options.i3_commands.push(value()?),
+            "--backend" => {
+                let value = value()?;
+                options.backend = BackendChoice::parse(&value)
+                    .with_context(|| format!("invalid --backend: {value} (auto|x11|shell)"))?;
+            }
+            "--ocr-engine" => {
+                let value = value()?;
+                options.ocr_engine = OcrChoice::parse(&value)
+                    .with_context(|| format!("invalid --ocr-engine: {value} (auto|cli|lib)"))?;
+            }
+            "--lang" => {
+                options.ocr.settings.languages =
+                    config::languages(&value()?).context("invalid --lang")?;
+            }
+            "--min-confidence" => {
+                options.ocr.settings.min_confidence =
+                    config::confidence(&value()?).context("invalid --min-confidence")?;
+            }
+            "--psm" => {
+                options.ocr.settings.psm = Some(config::psm(&value()?).context("invalid --psm")?);
+            }
+            "--oem" => {
+                options.ocr.settings.oem = Some(config::oem(&value()?).context("invalid --oem")?);
+            }
+            "--preprocess" => {
+                options.ocr.settings.preprocess =
+                    config::preprocess(&value()?).context("invalid --preprocess")?;
+            }
+            "--change-mode" => {
+                let value = value()?;
+                options.change.mode = ChangeMode::parse(&value).with_context(|| {
+                    format!("invalid --change-mode: {value} (exact|pixel|phash)")
+                })?;
+            }
+            "--tolerance" => {
+                let value = value()?;
+                options.change.tolerance =
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|t| (0.0..=1.0).contains(t))
+                        .with_context(|| format!("invalid --tolerance: {value} (fraction 0-1)"))?;
+            }
+            "--pixel-threshold" => {
+                let value = value()?;
+                options.change.pixel_threshold = value
+                    .parse()
+                    .with_context(|| format!("invalid --pixel-threshold: {value} (0-255)"))?;
+            }
+            "--phash-distance" => {
+                let value = value()?;
+                options.change.phash_distance = value
+                    .parse()
+                    .ok()
+                    .filter(|d| *d <= 64)
+                    .with_context(|| format!("invalid --phash-distance: {value} (0-64)"))?;
+            }
+            "--window" => options
+                .filter
+                .include
+                .push(filter::WindowPattern::parse(&value()?)?),
+            "--exclude" => options
+                .filter
+                .exclude
+                .push(filter::WindowPattern::parse(&value()?)?),
+            "--granularity" => {
+                let value = value()?;
+                options.granularity =
+                    granularity::Granularity::parse(&value).with_context(|| {
+                        format!("invalid --granularity: {value} (word|phrase|line|block)")
+                    })?;
+            }
+            "--jobs" => options.jobs = Some(parse_jobs(&value()?).context("invalid --jobs")?),
+            "--focused-only" => options.filter.focused_only = true,
+            "--region" => options.filter.region = Some(filter::parse_region(&value()?)?),
+            "--ignore-region" => options.change.ignore.push(IgnoreRegion::parse(&value()?)?),
+            other => bail!("unknown argument: {other}"),
         }
     }
+    Ok(())
+}
+
+fn parse_jobs(value: &str) -> Result<usize> {
+    value
+        .parse()
+        .ok()
+        .filter(|&jobs| jobs > 0)
+        .with_context(|| format!("{value} is not a positive number of jobs"))
 }
 
-/// Run OCR and extract both full text and per-word bounding boxes.
+/// Capture, diff and OCR every window against `previous`.
 ///
-/// Uses `tesseract tsv` output format which gives word-level positions.
-/// We convert element coordinates to absolute desktop coordinates by adding
-/// the window's geometry offset, so the AI can directly click on elements.
+/// `damaged` is the daemon's set of windows the X server reported as
+/// repainted since `previous` was taken; windows outside it are known to be
+/// unchanged and are not captured at all. `None` means "unknown", so every
+/// window is captured and hashed.
 ///
-/// Design decision: confidence threshold is 40%. Lower catches more text but
-/// adds noise tokens. Higher misses faint/small text. 40% was chosen as a
-/// balance after testing with XFCE default theme -- most real UI text scores
-/// >80%, while noise/artifacts score <30%.
-fn ocr_image_with_elements(image_path: &str, window_geom: &Geometry) -> (String, Vec<TextElement>) {
-    let output = Command::new("tesseract")
-        .args([image_path, "stdout", "-l", "eng+jpn", "tsv"])
-        .output();
-
-    match output {
-        Ok(out) if out.status.success() => {
-            let text = String::from_utf8_lossy(&out.stdout);
-            let mut elements = Vec::new();
-            let mut lines: Vec<(u32, Vec<String>)> = Vec::new();
-            let mut current_line: u32 = 0;
-            let mut current_words: Vec<String> = Vec::new();
-
-            for line in text.lines().skip(1) {
-                let parts: Vec<&str> = line.split('\t').collect();
-                if parts.len() < 12 {
-                    continue;
-                }
-                // TSV level 5 = word
-                let level: u32 = parts[0].parse().unwrap_or(0);
-                if level != 5 {
-                    continue;
-                }
-                let line_num: u32 = parts[4].parse().unwrap_or(0);
-                let conf: f32 = parts[10].parse().unwrap_or(-1.0);
-                let word = parts[11].trim();
-
-                if word.is_empty() || conf < 40.0 {
-                    continue;
-                }
+/// Returns the output to print and the state to persist for the next run.
+fn inspect(
+    backend: &mut dyn DesktopBackend,
+    ocr: &dyn OcrEngine,
+    previous: &PreviousState,
+    options: &Options,
+    screenshot_dir: &Path,
+    damaged: Option<&HashSet<String>>,
+) -> (InspectOutput, PreviousState) {
+    let timestamp = get_timestamp();
+    let desktop_size = backend.desktop_size();
+    let focused_window = backend.focused_window();
+    let window_list = backend.window_list();
+    let popup_list = backend.popup_list();
+    let stacking = backend.stacking_order();
 
-                let left: i32 = parts[6].parse().unwrap_or(0);
-                let top: i32 = parts[7].parse().unwrap_or(0);
-                let width: u32 = parts[8].parse().unwrap_or(0);
-                let height: u32 = parts[9].parse().unwrap_or(0);
-
-                elements.push(TextElement {
-                    text: word.to_string(),
-                    x: window_geom.x + left,
-                    y: window_geom.y + top,
-                    w: width,
-                    h: height,
-                    confidence: conf,
-                });
-
-                // Reconstruct text grouped by line
-                if line_num != current_line && !current_words.is_empty() {
-                    lines.push((current_line, std::mem::take(&mut current_words)));
-                    current_line = line_num;
-                } else if current_words.is_empty() {
-                    current_line = line_num;
-                }
-                current_words.push(word.to_string());
-            }
+    let mut events = match &previous.layout {
+        Some(layout) => events::WindowEvents::from_layout(layout, &window_list),
+        // An older state file knows the windows but not where they were:
+        // report no events rather than every window as opened.
+        None if !previous.windows.is_empty() => events::WindowEvents::default(),
+        None => events::WindowEvents::from_layout(&HashMap::new(), &window_list),
+    };
+    let mut new_state = PreviousState {
+        layout: Some(events::records(&window_list)),
+        ..PreviousState::default()
+    };
+    let mut changes = Vec::new();
 
-            if !current_words.is_empty() {
-                lines.push((current_line, current_words));
-            }
+    if let Err(e) = fs::create_dir_all(screenshot_dir) {
+        eprintln!("[lg-inspect] warning: failed to create screenshot dir: {e}");
+    }
 
-            let full_text = lines
-                .iter()
-                .map(|(_, words)| words.join(" "))
-                .collect::<Vec<_>>()
-                .join("\n");
+    // Everything on screen, reported or not.
+    let listed: Vec<&ListedWindow> = window_list.iter().chain(&popup_list).collect();
+    let screen = options
+        .screen
+        .then(|| {
+            read_screen(
+                backend,
+                ocr,
+                previous,
+                options,
+                &listed,
+                &stacking,
+                screenshot_dir,
+            )
+        })
+        .flatten();
+    if let Some(screen) = &screen {
+        screen.remember(&mut new_state);
+    }
 
-            (full_text, elements)
-        }
-        Ok(out) => {
-            eprintln!(
-                "[lg-inspect] warning: tesseract failed (exit {}): {}",
-                out.status,
-                String::from_utf8_lossy(&out.stderr).trim()
-            );
-            (String::new(), Vec::new())
+    let pass = Pass {
+        ocr,
+        previous,
+        options,
+        screenshot_dir,
+        screen: screen.as_ref(),
+    };
+    // Capture everything first (the backend is single-threaded), then OCR
+    // in parallel.
+    let mut captured = Vec::new();
+    let mut left_out = Vec::new();
+    for window in &window_list {
+        if options.filter.selects(window, &focused_window) {
+            let undamaged = damaged.is_some_and(|d| !d.contains(&window.id));
+            captured.push(pass.capture(backend, window, undamaged));
+        } else {
+            pass.keep(window, &mut new_state);
+            left_out.push(window.id.clone());
         }
-        Err(e) => {
-            eprintln!("[lg-inspect] warning: failed to run tesseract: {e}");
-            (String::new(), Vec::new())
+    }
+    let window_count = captured.len();
+    // The daemon's damage tracking only covers managed clients, and popups
+    // are short-lived anyway, so they are always captured.
+    for popup in &popup_list {
+        if options.filter.selects_popup(popup) {
+            captured.push(pass.capture(backend, popup, false));
+        } else {
+            pass.keep(popup, &mut new_state);
         }
     }
-}
+    let reads = pass.read_all(&captured);
+    options.ocr_cache.trim();
+    let mut windows: Vec<WindowInfo> = captured
+        .into_iter()
+        .zip(reads)
+        .map(|(c, read)| pass.finish(c, read, &mut new_state))
+        .collect();
+    let mut popups = windows.split_off(window_count);
+    for info in windows.iter().filter(|w| w.changed) {
+        changes.push(info.id.clone());
+        events.content_changed(&info.id);
+    }
 
-fn compute_hash(path: &str) -> String {
-    match fs::read(path) {
-        Ok(data) => {
-            let mut hasher = Sha256::new();
-            hasher.update(&data);
-            format!("{:x}", hasher.finalize())
-        }
-        Err(e) => {
-            eprintln!("[lg-inspect] warning: failed to read {path} for hashing: {e}");
-            String::new()
+    // Occluders include windows the filter left out.
+    occlusion::annotate(&mut windows, &mut popups, &listed, &stacking);
+
+    // Filter to changed windows only when --changes-only is set.
+    // This reduces JSON output significantly when only verifying an action result.
+    let mut unattributed = Vec::new();
+    if let Some(screen) = screen {
+        // Like unchanged windows, unchanged bar text is left out.
+        if !(options.changes_only && screen.unchanged) {
+            unattributed = granularity::group(screen.unattributed, &[], options.granularity);
         }
     }
+    if options.changes_only {
+        // Failures are never changes, but the caller has to know to look.
+        windows.retain(|w| w.changed || w.status.failed());
+        popups.retain(|w| w.changed || w.status.failed());
+    }
+
+    let output = InspectOutput {
+        timestamp,
+        desktop_size,
+        focused_window,
+        ocr_settings: options.ocr.settings.clone(),
+        windows,
+        popups,
+        changes_since_last: changes,
+        events,
+        unattributed,
+        i3: options.i3.then(i3_state).flatten(),
+        left_out,
+    };
+    (output, new_state)
 }
 
-fn load_previous_state() -> PreviousState {
-    match fs::read_to_string(STATE_PATH) {
-        Ok(content) => match serde_json::from_str(&content) {
-            Ok(state) => state,
-            Err(e) => {
-                eprintln!("[lg-inspect] warning: corrupt state file {STATE_PATH}, resetting: {e}");
-                PreviousState::default()
-            }
-        },
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => PreviousState::default(),
-        Err(e) => {
-            eprintln!("[lg-inspect] warning: failed to read state file {STATE_PATH}: {e}");
-            PreviousState::default()
-        }
-    }
+/// `--screen`: capture and OCR the whole screen with the global settings, or
+/// `None` to capture windows one by one when that fails.
+fn read_screen(
+    backend: &mut dyn DesktopBackend,
+    ocr: &dyn OcrEngine,
+    previous: &PreviousState,
+    options: &Options,
+    listed: &[&ListedWindow],
+    stacking: &[String],
+    screenshot_dir: &Path,
+) -> Option<screen::ScreenOcr> {
+    let reader = Reader {
+        engine: ocr,
+        settings: &options.ocr.settings,
+    };
+    screen::ScreenOcr::read(
+        backend,
+        reader,
+        &options.ocr_cache,
+        previous,
+        listed,
+        stacking,
+        screenshot_dir,
+    )
+    .map_err(|e| {
+        eprintln!(
+            "[lg-inspect] warning: screen capture failed, capturing windows instead: {}",
+            capture_status(e).1.unwrap_or_default()
+        );
+    })
+    .ok()
 }
 
-fn save_state(state: &PreviousState) -> Result<()> {
-    if let Some(parent) = std::path::Path::new(STATE_PATH).parent() {
-        fs::create_dir_all(parent)?;
+fn i3_state() -> Option<i3::I3State> {
+    i3::I3Ipc::connect()
+        .and_then(|mut ipc| ipc.state())
+        .map_err(|e| eprintln!("[lg-inspect] warning: could not read i3 state: {e:#}"))
+        .ok()
+}
+
+/// How long windows get to repaint after i3 rearranges them.
+const I3_SETTLE: Duration = Duration::from_millis(200);
+
+/// Run the `--i3-command`s, failing on the first one i3 rejects.
+///
+/// i3 replies once it has rearranged its containers, but the applications
+/// repaint afterwards; inspecting immediately would capture half-drawn
+/// windows, so we give them a moment first.
+fn run_i3_commands(options: &Options) -> Result<()> {
+    if options.i3_commands.is_empty() {
+        return Ok(());
     }
-    let json = serde_json::to_string_pretty(state)?;
-    let tmp_path = format!("{STATE_PATH}.tmp");
-    fs::write(&tmp_path, &json).context("failed to write temp state")?;
-    fs::rename(&tmp_path, STATE_PATH).context("failed to rename temp state to final")?;
+    let mut ipc = i3::I3Ipc::connect()?;
+    for command in &options.i3_commands {
+        ipc.command(command)?;
+    }
+    std::thread::sleep(I3_SETTLE);
     Ok(())
 }
 
-fn main() -> Result<()> {
-    // --changes-only: only include changed windows in output (reduces token overhead)
-    let changes_only = std::env::args().any(|a| a == "--changes-only");
+/// The inputs shared by every window of one inspection.
+struct Pass<'a> {
+    ocr: &'a dyn OcrEngine,
+    previous: &'a PreviousState,
+    options: &'a Options,
+    screenshot_dir: &'a Path,
+    /// The `--screen` capture, which replaces per-window captures and OCR.
+    screen: Option<&'a screen::ScreenOcr>,
+}
 
-    let timestamp = get_timestamp();
-    let desktop_size = get_desktop_size();
-    let focused_window = get_focused_window();
-    let window_list = get_window_list();
-    let previous = load_previous_state();
+/// A window after capture and change detection, waiting for OCR.
+struct Captured<'w> {
+    window: &'w ListedWindow,
+    /// `None` when the window is known clean or could not be captured.
+    capture: Option<Capture>,
+    status: WindowStatus,
+    reason: Option<String>,
+    is_changed: bool,
+    hash: Option<String>,
+    /// SHA256 of the captured pixels, for the OCR cache.
+    content: Option<String>,
+    screenshot_path: Option<String>,
+}
 
-    let mut new_state = PreviousState::default();
-    let mut windows = Vec::new();
-    let mut changes = Vec::new();
+/// The OCR result for one window and whether it came from the cache, or why
+/// OCR failed.
+type Read = Result<(Option<OcrResult>, bool), String>;
+
+impl Pass<'_> {
+    /// Capture one window and decide whether it changed. An `undamaged`
+    /// window with a previous hash is not captured at all.
+    fn capture<'w>(
+        &self,
+        backend: &mut dyn DesktopBackend,
+        window: &'w ListedWindow,
+        undamaged: bool,
+    ) -> Captured<'w> {
+        // Cropping from the screen capture costs nothing, so damage is
+        // only used to skip per-window captures.
+        let known_clean = (undamaged && self.screen.is_none())
+            .then(|| self.previous.windows.get(&window.id))
+            .flatten();
+        let previous_hash = self.previous.windows.get(&window.id);
 
-    for (id, geometry, title) in &window_list {
-        let screenshot_path = capture_window(id);
-        let (is_changed, ocr_text, elements) = match &screenshot_path {
-            Some(path) => {
-                let h = compute_hash(path);
-                let prev_hash = previous.windows.get(id).map(String::as_str);
-                let did_change = prev_hash != Some(&h);
-                let (ocr, elems) = if did_change {
-                    ocr_image_with_elements(path, geometry)
-                } else {
-                    (String::new(), Vec::new())
-                };
-                new_state.windows.insert(id.clone(), h);
-                (did_change, ocr, elems)
+        let mut status = WindowStatus::Ok;
+        let mut reason = None;
+        let capture = match known_clean {
+            Some(_) => None,
+            None => match self
+                .screen
+                .map_or_else(|| backend.capture_window(window), |s| s.crop(window))
+            {
+                Ok(capture) => Some(capture),
+                Err(e) => {
+                    (status, reason) = capture_status(e);
+                    None
+                }
+            },
+        };
+        let (is_changed, hash, content, screenshot_path) = match &capture {
+            Some(capture) => {
+                let (verdict, path) = judge_capture(
+                    self.options,
+                    window,
+                    &capture.image,
+                    previous_hash.map(String::as_str),
+                    self.screenshot_dir,
+                );
+                (
+                    verdict.changed,
+                    Some(verdict.hash),
+                    Some(verdict.content),
+                    path,
+                )
             }
-            None => (true, String::new(), Vec::new()),
+            // Known clean, or not capturable right now: keep the previous
+            // hash so the window is not reported as changed once it is back.
+            None => (false, previous_hash.cloned(), None, None),
         };
+        Captured {
+            window,
+            capture,
+            status,
+            reason,
+            is_changed,
+            hash,
+            content,
+            screenshot_path,
+        }
+    }
+
+    /// OCR every captured window on up to `options.jobs()` threads,
+    /// returning the results in the order of `captured`.
+    ///
+    /// Windows are handed out one at a time from a shared counter, so a
+    /// slow window does not hold up a queue of others behind it.
+    fn read_all(&self, captured: &[Captured]) -> Vec<Read> {
+        let jobs = self.options.jobs().min(captured.len());
+        if jobs <= 1 {
+            return captured.iter().map(|c| self.read(c)).collect();
+        }
+        let next = AtomicUsize::new(0);
+        let mut reads: Vec<(usize, Read)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(c) = captured.get(i) else {
+                                return done;
+                            };
+                            done.push((i, self.read(c)));
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("OCR worker panicked"))
+                .collect()
+        });
+        reads.sort_by_key(|(i, _)| *i);
+        reads.into_iter().map(|(_, read)| read).collect()
+    }
 
-        if is_changed {
-            changes.push(id.clone());
+    /// Record a window's hash and OCR result in `new_state` and build its
+    /// report.
+    fn finish(&self, captured: Captured, read: Read, new_state: &mut PreviousState) -> WindowInfo {
+        let Captured {
+            window,
+            capture,
+            mut status,
+            mut reason,
+            is_changed,
+            hash,
+            screenshot_path,
+            ..
+        } = captured;
+        let ListedWindow {
+            id,
+            geometry,
+            title,
+            ..
+        } = window;
+        let (result, cached) = match read {
+            Ok(read) => read,
+            Err(e) => {
+                status = WindowStatus::OcrFailed;
+                reason = Some(e);
+                (None, false)
+            }
+        };
+        if status == WindowStatus::OcrFailed {
+            // Nothing was read, so nothing is reported as changed: keep the
+            // last good state and the next run retries and reports it.
+            self.keep(window, new_state);
+        } else if let Some(h) = hash {
+            if let Some(path) = screenshot_path
+                .as_deref()
+                .filter(|_| is_changed && self.options.change.mode == ChangeMode::Pixel)
+            {
+                promote_baseline(path, self.screenshot_dir, id);
+            }
+            // An uncapturable window keeps its last OCR for when it returns.
+            let kept = result
+                .as_ref()
+                .or(self.previous.ocr_of(id, &h).filter(|_| capture.is_none()));
+            if let Some(kept) = kept {
+                new_state.remember_ocr(id, &h, kept.clone());
+            }
+            new_state.windows.insert(id.clone(), h);
+        }
+        let result = result.unwrap_or_default();
+        let is_changed = is_changed && status == WindowStatus::Ok;
+        if status == WindowStatus::Ok && !is_changed {
+            status = WindowStatus::Unchanged;
         }
 
-        windows.push(WindowInfo {
+        let mut info = WindowInfo {
             id: id.clone(),
             title: title.clone(),
             geometry: *geometry,
-            ocr_text,
-            elements,
+            desktop: (window.desktop >= 0).then_some(window.desktop),
+            meta: window.meta.clone(),
+            elements: Vec::new(),
+            ocr_text: String::new(),
             changed: is_changed,
-        });
+            status,
+            reason,
+            cached,
+            ocr_settings: Some(self.settings(window)).filter(|s| *s != self.options.ocr.settings),
+            preprocessed: result.preprocessed,
+            diff: None,
+            z_index: None,
+            visibility: occlusion::Visibility::Visible,
+            layout: Vec::new(),
+        };
+        if self.options.diff && is_changed {
+            info.diff = Some(diff::diff(self.previous_result(id), &result, geometry));
+        } else {
+            let words = result.absolute_elements(geometry);
+            if self.options.layout {
+                info.layout = layout::tree(&words, &result.lines);
+            }
+            info.elements = granularity::group(words, &result.lines, self.options.granularity);
+            info.ocr_text = result.text;
+        }
+        info
     }
 
-    // Restore original focus after capturing all windows
-    if !focused_window.is_empty() {
-        let _ = Command::new("xdotool")
-            .args(["windowfocus", "--sync", &focused_window])
-            .status();
+    /// Carry a window the filter left out over to `new_state` untouched.
+    fn keep(&self, window: &ListedWindow, new_state: &mut PreviousState) {
+        let Some(hash) = self.previous.windows.get(&window.id) else {
+            return;
+        };
+        if let Some(result) = self.previous.ocr_of(&window.id, hash) {
+            new_state.remember_ocr(&window.id, hash, result.clone());
+        }
+        new_state.windows.insert(window.id.clone(), hash.clone());
     }
 
-    if let Err(e) = save_state(&new_state) {
-        eprintln!("[lg-inspect] warning: failed to save state: {e}");
+    /// The OCR settings `window` is read with; `--screen` reads everything
+    /// in one OCR, with the global settings.
+    fn settings(&self, window: &ListedWindow) -> OcrSettings {
+        match self.screen {
+            Some(_) => self.options.ocr.settings.clone(),
+            None => self.options.ocr.for_window(window),
+        }
     }
 
-    // Filter to changed windows only when --changes-only is set.
-    // This reduces JSON output significantly when only verifying an action result.
-    let filtered_windows = if changes_only {
-        windows.into_iter().filter(|w| w.changed).collect()
+    fn previous_result(&self, id: &str) -> Option<&OcrResult> {
+        self.previous
+            .windows
+            .get(id)
+            .and_then(|h| self.previous.ocr_of(id, h))
+    }
+
+    /// OCR one captured window, or reuse the cached result of identical
+    /// pixels.
+    fn read(&self, captured: &Captured) -> Read {
+        let Captured {
+            window,
+            capture,
+            status,
+            is_changed,
+            hash,
+            content,
+            screenshot_path,
+            ..
+        } = captured;
+        if let Some(screen) = self.screen {
+            return match (&screen.error, capture) {
+                (_, None) => Ok((None, false)),
+                (Some(e), Some(_)) => Err(e.clone()),
+                (None, Some(_)) => Ok((Some(screen.window_result(window)), screen.cached)),
+            };
+        }
+        let settings = self.settings(window);
+        let reader = Reader {
+            engine: self.ocr,
+            settings: &settings,
+        };
+        // Unchanged pixels: reuse the OCR of the identical capture.
+        if let Some(result) = hash
+            .as_ref()
+            .filter(|_| !is_changed && *status == WindowStatus::Ok)
+            .and_then(|h| self.previous.ocr_of(&window.id, h))
+            .filter(|r| r.settings == settings.fingerprint())
+        {
+            return Ok((Some(result.clone()), true));
+        }
+        match (screenshot_path, capture, content) {
+            // Changed, or unchanged but never OCR'd (state from an older
+            // version); skip the latter when nobody will see the text.
+            (Some(path), Some(capture), Some(content))
+                if *is_changed || !self.options.changes_only =>
+            {
+                // Pixels read before, by this window or another: the cache
+                // holds elements relative to the capture, not the window.
+                let key = cache::key(content, &settings);
+                let [dx, dy] = capture.offset.map(|o| i32::try_from(o).unwrap_or(0));
+                if let Some(result) = self.options.ocr_cache.get(&key) {
+                    return Ok((Some(result.shifted(dx, dy)), true));
+                }
+                ocr_capture(
+                    reader,
+                    capture,
+                    path,
+                    self.previous_result(&window.id),
+                    self.screenshot_dir,
+                    &window.id,
+                )
+                .map(|result| {
+                    self.options.ocr_cache.put(&key, &result.shifted(-dx, -dy));
+                    (Some(result), false)
+                })
+                .map_err(|e| {
+                    eprintln!(
+                        "[lg-inspect] warning: OCR failed for window {}: {e:#}",
+                        window.id
+                    );
+                    format!("{e:#}")
+                })
+            }
+            _ => Ok((None, false)),
+        }
+    }
+}
+
+/// Map a capture error to the window's status and reason.
+fn capture_status(error: CaptureError) -> (WindowStatus, Option<String>) {
+    match error {
+        CaptureError::Minimized(reason) => (WindowStatus::Minimized, Some(reason)),
+        CaptureError::Offscreen(reason) => (WindowStatus::Offscreen, Some(reason)),
+        CaptureError::Failed(reason) => (WindowStatus::CaptureFailed, Some(reason)),
+    }
+}
+
+/// The capture `pixel` mode compares a window against: the last change that
+/// was read.
+fn baseline_path(screenshot_dir: &Path, window_id: &str) -> PathBuf {
+    screenshot_dir.join(format!("{window_id}.baseline.png"))
+}
+
+/// Decide whether `image` changed and return the verdict and the path of the
+/// capture to OCR.
+fn judge_capture(
+    options: &Options,
+    window: &ListedWindow,
+    image: &RgbaImage,
+    previous_hash: Option<&str>,
+    screenshot_dir: &Path,
+) -> (change::Verdict, Option<String>) {
+    let baseline = baseline_path(screenshot_dir, &window.id);
+    let verdict = options
+        .change
+        .judge(window, image, previous_hash, &baseline);
+    let saved = screenshot_dir.join(format!("{}.png", window.id));
+    let path = if verdict.changed || !saved.exists() {
+        save_capture(image, screenshot_dir, &window.id)
     } else {
-        windows
+        Some(saved.to_string_lossy().into_owned())
     };
+    (verdict, path)
+}
 
-    let output = InspectOutput {
-        timestamp,
-        desktop_size,
-        focused_window,
-        windows: filtered_windows,
-        changes_since_last: changes,
+/// Make the capture at `path` the window's `pixel` mode baseline. Only done
+/// once the change has been read, so a failed OCR leaves the old baseline
+/// and the next run still sees the change.
+fn promote_baseline(path: &str, screenshot_dir: &Path, window_id: &str) {
+    if let Err(e) = fs::copy(path, baseline_path(screenshot_dir, window_id)) {
+        eprintln!("[lg-inspect] warning: failed to save baseline for window {window_id}: {e}");
+    }
+}
+
+/// OCR a changed capture, re-reading only its dirty bands when the window's
+/// previous result allows it.
+fn ocr_capture(
+    reader: Reader,
+    capture: &Capture,
+    path: &str,
+    previous: Option<&OcrResult>,
+    screenshot_dir: &Path,
+    window_id: &str,
+) -> Result<OcrResult> {
+    let image = &capture.image;
+    // Elements are stored relative to the window (see OcrResult), so OCR
+    // coordinates within the capture are shifted by where it sits inside the
+    // window.
+    let origin = Geometry {
+        x: i32::try_from(capture.offset[0]).unwrap_or(0),
+        y: i32::try_from(capture.offset[1]).unwrap_or(0),
+        w: image.width(),
+        h: image.height(),
     };
+    let tiles = tiles::tile_hashes(image);
+    let partial = previous.and_then(|prev| {
+        tiles::ocr_dirty(
+            reader,
+            image,
+            &origin,
+            &tiles,
+            prev,
+            screenshot_dir,
+            window_id,
+        )
+    });
+    if let Some(result) = partial {
+        return Ok(result);
+    }
+    let result = reader.read(image, path, &origin)?;
+    Ok(OcrResult { tiles, ..result })
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let env = client_env();
+    let options = parse_args(args.clone(), &env)?;
+    if options.daemon {
+        return daemon::run(&options);
+    }
+    // The daemon captures through its own X connection only.
+    if !options.no_daemon && options.backend != BackendChoice::Shell {
+        if let Some(response) = daemon::query(&args, &env) {
+            println!("{}", response?);
+            return Ok(());
+        }
+    }
+
+    run_i3_commands(&options)?;
+    let mut backend = backend::connect(options.backend)?;
+    let engine = ocr::engine(options.ocr_engine, &options.ocr.settings)?;
+    let previous = load_previous_state();
+
+    let (output, new_state) = inspect(
+        backend.as_mut(),
+        engine.as_ref(),
+        &previous,
+        &options,
+        Path::new(SCREENSHOT_DIR),
+        None,
+    );
+
+    if let Err(e) = save_state(&new_state) {
+        eprintln!("[lg-inspect] warning: failed to save state: {e}");
+    }
 
     let json = serde_json::to_string_pretty(&output)?;
     println!("{json}");
@@ -520,4 +1311,922 @@ mod tests {
         assert_eq!(g.x, g2.x);
         assert_eq!(g.w, g2.w);
     }
+
+    /// One OCR job, so `FakeOcr::calls` come in window order.
+    fn options() -> Options {
+        Options {
+            jobs: Some(1),
+            ocr_cache: cache::OcrCache::disabled(),
+            ..Options::default()
+        }
+    }
+
+    /// `options()` with `args` applied; unlike `parse_args`, independent of
+    /// the environment and the config file.
+    fn with_flags(args: &[&str]) -> Result<Options> {
+        apply_flags(options(), args.iter().map(ToString::to_string))
+    }
+
+    fn run(
+        desktop: &mut fake::FakeDesktop,
+        ocr: &fake::FakeOcr,
+        previous: &PreviousState,
+        options: &Options,
+        dir: &Path,
+    ) -> (InspectOutput, PreviousState) {
+        inspect(desktop, ocr, previous, options, dir, None)
+    }
+
+    #[test]
+    fn test_inspect_first_run_ocrs_every_window() {
+        let dir = fake::scratch_dir("first-run");
+        let mut desktop = fake::two_window_desktop();
+        let ocr = fake::fixture_ocr();
+        let (output, state) = run(
+            &mut desktop,
+            &ocr,
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+
+        assert_eq!(output.desktop_size, [1280, 1024]);
+        assert_eq!(output.focused_window, "0x00000002");
+        assert_eq!(output.changes_since_last, ["0x00000001", "0x00000002"]);
+        assert!(output.windows.iter().all(|w| w.changed));
+        assert_eq!(
+            output.windows[0].ocr_text,
+            "user@host:~$ ls\nDocuments Downloads"
+        );
+        // Dialog elements are offset by the window position.
+        let cancel = output.windows[1]
+            .elements
+            .iter()
+            .find(|e| e.text == "Cancel")
+            .unwrap();
+        assert_eq!((cancel.x, cancel.y), (700 + 220, 300 + 150));
+        assert_eq!(ocr.calls(), ["0x00000001", "0x00000002"]);
+        assert_eq!(state.windows.len(), 2);
+    }
+
+    #[test]
+    fn test_inspect_unchanged_windows_report_cached_ocr() {
+        let dir = fake::scratch_dir("unchanged");
+        let mut desktop = fake::two_window_desktop();
+        let (_, state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+
+        let ocr = fake::fixture_ocr();
+        let (output, _) = run(&mut desktop, &ocr, &state, &options(), &dir);
+        assert!(output.changes_since_last.is_empty());
+        assert!(output.windows.iter().all(|w| !w.changed && w.cached));
+        assert_eq!(
+            output.windows[0].ocr_text,
+            "user@host:~$ ls\nDocuments Downloads"
+        );
+        assert_eq!(output.windows[1].elements.len(), 6);
+        assert!(ocr.calls().is_empty());
+    }
+
+    #[test]
+    fn test_inspect_flat_windows_keep_their_own_ocr_in_phash_mode() {
+        // Both windows are flat, so both perceptual hashes are zero.
+        let dir = fake::scratch_dir("phash-flat");
+        let mut desktop = fake::two_window_desktop();
+        let phash = Options {
+            change: ChangeConfig {
+                mode: ChangeMode::Perceptual,
+                ..ChangeConfig::default()
+            },
+            ..options()
+        };
+        let (_, state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &phash,
+            &dir,
+        );
+        assert_eq!(state.windows["0x00000001"], state.windows["0x00000002"]);
+
+        let ocr = fake::fixture_ocr();
+        let (output, _) = run(&mut desktop, &ocr, &state, &phash, &dir);
+        assert!(ocr.calls().is_empty());
+        assert!(output.windows.iter().all(|w| !w.changed && w.cached));
+        assert_eq!(
+            output.windows[0].ocr_text,
+            "user@host:~$ ls\nDocuments Downloads"
+        );
+        assert_eq!(output.windows[1].elements.len(), 6);
+    }
+
+    #[test]
+    fn test_inspect_reports_line_elements() {
+        let dir = fake::scratch_dir("granularity");
+        let mut desktop = fake::two_window_desktop();
+        let lines = with_flags(&["--granularity", "line"]).unwrap();
+        let (output, state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &lines,
+            &dir,
+        );
+
+        let dialog = &output.windows[1];
+        let ids: Vec<_> = dialog.elements.iter().map(|e| e.id.as_deref()).collect();
+        assert_eq!(
+            ids,
+            [None, None, None, None, None, None, Some("l0"), Some("l1")]
+        );
+        assert_eq!(dialog.elements[4].text, "Cancel");
+        let buttons = &dialog.elements[7];
+        assert_eq!(buttons.text, "Cancel Save");
+        assert_eq!((buttons.x, buttons.y), (700 + 220, 300 + 150));
+        // The state keeps words, so any granularity can be served later.
+        let hash = &state.windows["0x00000002"];
+        assert_eq!(state.ocr_of("0x00000002", hash).unwrap().elements.len(), 6);
+        assert!(with_flags(&["--granularity", "letter"]).is_err());
+    }
+
+    #[test]
+    fn test_inspect_applies_per_window_ocr_settings() {
+        let dir = fake::scratch_dir("ocr-settings");
+        let mut desktop = fake::two_window_desktop();
+        let configured = Options {
+            ocr: config::OcrConfig::parse(
+                r#"{"ocr": {"languages": "eng"}, "windows": [{"match": "^Save", "ocr": {"psm": 11}}]}"#,
+            )
+            .unwrap(),
+            ..options()
+        };
+        let ocr = fake::fixture_ocr();
+        let (output, state) = run(
+            &mut desktop,
+            &ocr,
+            &PreviousState::default(),
+            &configured,
+            &dir,
+        );
+
+        assert_eq!(output.ocr_settings.languages, "eng");
+        assert_eq!(
+            ocr.settings("0x00000001"),
+            Some(output.ocr_settings.clone())
+        );
+        let dialog = ocr.settings("0x00000002").unwrap();
+        assert_eq!((dialog.languages.as_str(), dialog.psm), ("eng", Some(11)));
+        assert!(output.windows[0].ocr_settings.is_none());
+        assert_eq!(output.windows[1].ocr_settings, Some(dialog));
+
+        // Unchanged pixels read with other settings are read again.
+        let ocr = fake::fixture_ocr();
+        let (output, _) = run(&mut desktop, &ocr, &state, &options(), &dir);
+        assert!(output.windows.iter().all(|w| !w.changed && !w.cached));
+        assert_eq!(ocr.calls(), ["0x00000001", "0x00000002"]);
+    }
+
+    #[test]
+    fn test_inspect_retries_blank_window_preprocessed() {
+        let dir = fake::scratch_dir("preprocess");
+        let mut desktop = fake::two_window_desktop();
+        // Read at twice the size, as the preprocessed image is.
+        let tsv = format!(
+            "{}5\t1\t1\t1\t1\t1\t40\t20\t120\t28\t88.0\tuser@host:~$\n",
+            ocr::TSV_HEADER
+        );
+        let ocr = fake::fixture_ocr()
+            .with("0x00000002", ocr::TSV_HEADER)
+            .with("0x00000002.pre", &tsv);
+        let (output, _) = run(
+            &mut desktop,
+            &ocr,
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+
+        assert_eq!(ocr.calls(), ["0x00000001", "0x00000002", "0x00000002.pre"]);
+        let terminal = &output.windows[1];
+        assert!(terminal.preprocessed && !output.windows[0].preprocessed);
+        assert_eq!(terminal.ocr_text, "user@host:~$");
+        let prompt = &terminal.elements[0];
+        assert_eq!(
+            (prompt.x, prompt.y, prompt.w, prompt.h),
+            (700 + 20, 300 + 10, 60, 14)
+        );
+    }
+
+    #[test]
+    fn test_inspect_reopened_window_hits_ocr_cache() {
+        let dir = fake::scratch_dir("ocr-cache-reopen");
+        let cached = Options {
+            ocr_cache: cache::OcrCache::new(dir.join("cache"), cache::DEFAULT_MAX_BYTES),
+            ..options()
+        };
+        let mut desktop = fake::two_window_desktop();
+        let (_, state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &cached,
+            &dir,
+        );
+
+        // The dialog closes, then reopens as a new window elsewhere.
+        let mut closed = fake::two_window_desktop();
+        closed.windows.truncate(1);
+        let (_, state) = run(&mut closed, &fake::fixture_ocr(), &state, &cached, &dir);
+        assert!(!state.windows.contains_key("0x00000002"));
+        let mut reopened = fake::two_window_desktop().window(
+            "0x00000003",
+            "Save?",
+            Geometry {
+                x: 100,
+                y: 50,
+                w: 400,
+                h: 200,
+            },
+            [255, 255, 255],
+        );
+        reopened.windows.remove(1);
+
+        let ocr = fake::FakeOcr::default();
+        let (output, _) = run(&mut reopened, &ocr, &state, &cached, &dir);
+        let dialog = &output.windows[1];
+        assert!(dialog.changed && dialog.cached);
+        assert!(ocr.calls().is_empty());
+        let save = dialog
+            .elements
+            .iter()
+            .find(|e| e.text == "Save" && e.y > 100)
+            .unwrap();
+        assert_eq!((save.x, save.y), (100 + 300, 50 + 150));
+    }
+
+    #[test]
+    fn test_inspect_cached_elements_follow_moved_window() {
+        let dir = fake::scratch_dir("moved");
+        let mut desktop = fake::two_window_desktop();
+        let (_, state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+
+        desktop.windows[1].geometry.x = 100;
+        let (output, _) = run(&mut desktop, &fake::fixture_ocr(), &state, &options(), &dir);
+        let cancel = output.windows[1]
+            .elements
+            .iter()
+            .find(|e| e.text == "Cancel")
+            .unwrap();
+        assert_eq!((cancel.x, cancel.y), (100 + 220, 300 + 150));
+    }
+
+    #[test]
+    fn test_inspect_changes_only_reports_repainted_window() {
+        let dir = fake::scratch_dir("changes-only");
+        let mut desktop = fake::two_window_desktop();
+        let (_, state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+
+        desktop.paint("0x00000002", fake::solid(400, 200, [200, 200, 200]));
+        let ocr = fake::fixture_ocr();
+        let changes_only = Options {
+            changes_only: true,
+            ..options()
+        };
+        let (output, _) = run(&mut desktop, &ocr, &state, &changes_only, &dir);
+        assert_eq!(output.changes_since_last, ["0x00000002"]);
+        assert_eq!(output.windows.len(), 1);
+        assert_eq!(output.windows[0].id, "0x00000002");
+        assert_eq!(ocr.calls(), ["0x00000002"]);
+    }
+
+    #[test]
+    fn test_inspect_reocrs_only_dirty_bands() {
+        let dir = fake::scratch_dir("dirty-bands");
+        let mut desktop = fake::two_window_desktop();
+        let (_, state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+
+        // Repaint a strip near the bottom of the terminal, away from the
+        // fixture's two lines of text.
+        let mut typed = fake::solid(640, 480, [0, 0, 0]);
+        for y in 400..410 {
+            for x in 0..80 {
+                typed.put_pixel(x, y, image::Rgba([255, 255, 255, 255]));
+            }
+        }
+        desktop.paint("0x00000001", typed);
+        let ocr = fake::FakeOcr::default().with("0x00000001.tile0", "");
+        let (output, _) = run(&mut desktop, &ocr, &state, &options(), &dir);
+        assert_eq!(output.changes_since_last, ["0x00000001"]);
+        assert_eq!(ocr.calls(), ["0x00000001.tile0"]);
+        assert_eq!(output.windows[0].elements.len(), 4);
+        assert_eq!(
+            output.windows[0].ocr_text,
+            "user@host:~$ ls\nDocuments Downloads"
+        );
+    }
+
+    #[test]
+    fn test_inspect_diff_reports_only_new_text() {
+        let dir = fake::scratch_dir("diff");
+        let mut desktop = fake::two_window_desktop();
+        let (_, state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+
+        // The terminal prints a third line.
+        desktop.paint("0x00000001", fake::solid(640, 480, [0, 0, 40]));
+        let tsv = format!(
+            "{}5\t1\t1\t1\t3\t1\t4\t40\t48\t14\t93.0\tPictures\n",
+            include_str!("../fixtures/terminal.tsv")
+        );
+        let ocr = fake::fixture_ocr().with("0x00000001", &tsv);
+        let diff_only = Options {
+            diff: true,
+            changes_only: true,
+            ..options()
+        };
+        let (output, _) = run(&mut desktop, &ocr, &state, &diff_only, &dir);
+        assert_eq!(output.windows.len(), 1);
+        let window = &output.windows[0];
+        assert!(window.elements.is_empty() && window.ocr_text.is_empty());
+        let diff = window.diff.as_ref().unwrap();
+        assert_eq!(diff.added_lines, ["Pictures"]);
+        assert_eq!(diff.added.len(), 1);
+        assert!(diff.removed.is_empty() && diff.moved.is_empty());
+    }
+
+    #[test]
+    fn test_inspect_elements_of_window_off_left_edge() {
+        let dir = fake::scratch_dir("off-edge");
+        let mut desktop = fake::two_window_desktop();
+        // Only the right 300px of the dialog are on screen; OCR positions
+        // are relative to that visible part.
+        desktop.windows[1].geometry.x = -100;
+        let (output, _) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+        let cancel = output.windows[1]
+            .elements
+            .iter()
+            .find(|e| e.text == "Cancel")
+            .unwrap();
+        assert_eq!((cancel.x, cancel.y), (220, 300 + 150));
+    }
+
+    #[test]
+    fn test_inspect_reports_popups() {
+        let dir = fake::scratch_dir("popups");
+        let mut desktop = fake::two_window_desktop().popup(
+            "0x00a00007",
+            Geometry {
+                x: 300,
+                y: 100,
+                w: 200,
+                h: 120,
+            },
+            [240, 240, 240],
+        );
+        let menu = format!(
+            "{}\n5\t1\t1\t1\t1\t1\t8\t4\t40\t14\t96.0\tCopy\n",
+            include_str!("../fixtures/terminal.tsv")
+                .lines()
+                .next()
+                .unwrap()
+        );
+        let ocr = fake::fixture_ocr().with("0x00a00007", &menu);
+        let (output, state) = run(
+            &mut desktop,
+            &ocr,
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+        assert_eq!(output.windows.len(), 2);
+        assert_eq!(output.popups.len(), 1);
+        let copy = &output.popups[0].elements[0];
+        assert_eq!((copy.text.as_str(), copy.x, copy.y), ("Copy", 308, 104));
+        assert!(!output
+            .changes_since_last
+            .contains(&"0x00a00007".to_string()));
+
+        // Popups are captured even when the daemon saw no damage.
+        desktop.captures.clear();
+        let (output, _) = inspect(
+            &mut desktop,
+            &ocr,
+            &state,
+            &options(),
+            &dir,
+            Some(&HashSet::new()),
+        );
+        assert_eq!(desktop.captures, ["0x00a00007"]);
+        assert_eq!(output.popups[0].status, WindowStatus::Unchanged);
+    }
+
+    #[test]
+    fn test_inspect_reports_window_events() {
+        let dir = fake::scratch_dir("events");
+        let mut desktop = fake::two_window_desktop();
+        let (first, state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+        assert_eq!(first.events.opened, ["0x00000001", "0x00000002"]);
+        assert!(first.events.content_changed.is_empty());
+
+        // The dialog closes and the terminal moves without repainting.
+        desktop.windows.pop();
+        desktop.windows[0].geometry.x = 50;
+        let (output, _) = run(&mut desktop, &fake::fixture_ocr(), &state, &options(), &dir);
+        assert!(output.events.opened.is_empty());
+        assert_eq!(output.events.closed.len(), 1);
+        assert_eq!(output.events.closed[0].title, "Save?");
+        assert_eq!(output.events.moved, ["0x00000001"]);
+        assert!(output.events.resized.is_empty());
+        assert!(output.changes_since_last.is_empty());
+    }
+
+    #[test]
+    fn test_inspect_state_without_layout_reports_no_events() {
+        let dir = fake::scratch_dir("old-state");
+        let mut desktop = fake::two_window_desktop();
+        let (_, state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+        // As written before window layouts were recorded.
+        let mut json = serde_json::to_value(&state).unwrap();
+        json.as_object_mut().unwrap().remove("layout");
+        let old: PreviousState = serde_json::from_value(json).unwrap();
+
+        desktop.windows.pop();
+        let (output, state) = run(&mut desktop, &fake::fixture_ocr(), &old, &options(), &dir);
+        assert!(output.events.opened.is_empty());
+        assert!(output.events.closed.is_empty());
+        assert!(output.changes_since_last.is_empty());
+
+        // The layout is recorded from then on.
+        desktop.windows[0].geometry.x = 50;
+        let (output, _) = run(&mut desktop, &fake::fixture_ocr(), &state, &options(), &dir);
+        assert_eq!(output.events.moved, ["0x00000001"]);
+    }
+
+    #[test]
+    fn test_inspect_capture_failure_is_not_a_change() {
+        let dir = fake::scratch_dir("capture-failure");
+        let mut desktop = fake::two_window_desktop();
+        let (_, state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+
+        desktop.images.remove("0x00000001");
+        desktop.failures.insert(
+            "0x00000001".to_string(),
+            CaptureError::Minimized("window is minimized".to_string()),
+        );
+        let (output, new_state) = run(&mut desktop, &fake::fixture_ocr(), &state, &options(), &dir);
+        let terminal = &output.windows[0];
+        assert_eq!(terminal.status, WindowStatus::Minimized);
+        assert_eq!(terminal.reason.as_deref(), Some("window is minimized"));
+        assert!(!terminal.changed && terminal.ocr_text.is_empty());
+        assert!(output.changes_since_last.is_empty());
+
+        // Restored unchanged: not a change, and the old OCR is still cached.
+        desktop.failures.clear();
+        desktop.paint("0x00000001", fake::solid(640, 480, [0, 0, 0]));
+        let (output, _) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &new_state,
+            &options(),
+            &dir,
+        );
+        assert_eq!(output.windows[0].status, WindowStatus::Unchanged);
+        assert!(output.windows[0].cached);
+    }
+
+    #[test]
+    fn test_inspect_ocr_failure_status() {
+        let dir = fake::scratch_dir("ocr-failure");
+        let mut desktop = fake::two_window_desktop();
+        let ocr =
+            fake::FakeOcr::default().with("0x00000002", include_str!("../fixtures/dialog.tsv"));
+        let changes_only = Options {
+            changes_only: true,
+            ..options()
+        };
+        let (output, state) = run(
+            &mut desktop,
+            &ocr,
+            &PreviousState::default(),
+            &changes_only,
+            &dir,
+        );
+        // Kept by --changes-only even though it is not a change.
+        assert_eq!(output.windows.len(), 2);
+        assert_eq!(output.windows[0].status, WindowStatus::OcrFailed);
+        assert!(output.windows[0]
+            .reason
+            .as_ref()
+            .unwrap()
+            .contains("no canned TSV"));
+        assert!(!output.windows[0].changed);
+        assert_eq!(output.changes_since_last, ["0x00000002"]);
+        assert_eq!(output.windows[1].status, WindowStatus::Ok);
+        // Not remembered, so the next run tries again and reports the change.
+        assert!(!state.windows.contains_key("0x00000001"));
+        let (output, _) = run(&mut desktop, &fake::fixture_ocr(), &state, &options(), &dir);
+        assert_eq!(output.windows[0].status, WindowStatus::Ok);
+        assert!(output.windows[0].changed);
+        assert_eq!(output.changes_since_last, ["0x00000001"]);
+    }
+
+    #[test]
+    fn test_inspect_json_shape() {
+        let dir = fake::scratch_dir("json-shape");
+        let mut desktop = fake::two_window_desktop();
+        let blank = fake::FakeOcr::default();
+        let (output, _) = run(
+            &mut desktop,
+            &blank,
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+
+        let json = serde_json::to_value(&output).unwrap();
+        let window = &json["windows"][0];
+        assert_eq!(window["id"], "0x00000001");
+        assert_eq!(window["geometry"]["w"], 640);
+        assert_eq!(window["changed"], false);
+        assert_eq!(window["status"], "ocr_failed");
+        // Empty element lists and the default cached flag are omitted to save tokens.
+        assert!(window.get("elements").is_none());
+        assert!(window.get("cached").is_none());
+    }
+
+    #[test]
+    fn test_inspect_pixel_mode_ignores_caret_blink() {
+        let dir = fake::scratch_dir("caret");
+        let mut desktop = fake::two_window_desktop();
+        let pixel = Options {
+            change: ChangeConfig {
+                mode: ChangeMode::Pixel,
+                ..ChangeConfig::default()
+            },
+            ..options()
+        };
+        let (_, state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &pixel,
+            &dir,
+        );
+
+        let mut caret = fake::solid(640, 480, [0, 0, 0]);
+        for y in 40..56 {
+            caret.put_pixel(120, y, image::Rgba([255, 255, 255, 255]));
+        }
+        desktop.paint("0x00000001", caret);
+        let ocr = fake::fixture_ocr();
+        let (output, new_state) = run(&mut desktop, &ocr, &state, &pixel, &dir);
+        assert!(output.changes_since_last.is_empty());
+        assert!(ocr.calls().is_empty());
+        // The baseline stays put so the caret cannot creep past the tolerance.
+        assert_eq!(new_state.windows["0x00000001"], state.windows["0x00000001"]);
+    }
+
+    #[test]
+    fn test_inspect_pixel_mode_retries_change_after_ocr_failure() {
+        let dir = fake::scratch_dir("pixel-ocr-failure");
+        let mut desktop = fake::two_window_desktop();
+        let pixel = Options {
+            change: ChangeConfig {
+                mode: ChangeMode::Pixel,
+                ..ChangeConfig::default()
+            },
+            ..options()
+        };
+        let (_, state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &pixel,
+            &dir,
+        );
+
+        desktop.paint("0x00000001", fake::solid(640, 480, [255, 255, 255]));
+        let failing = fake::FakeOcr::default();
+        let (output, state) = run(&mut desktop, &failing, &state, &pixel, &dir);
+        assert_eq!(output.windows[0].status, WindowStatus::OcrFailed);
+
+        // The failed capture did not become the baseline, so the change is
+        // still seen and read.
+        let ocr = fake::fixture_ocr();
+        let (output, _) = run(&mut desktop, &ocr, &state, &pixel, &dir);
+        assert_eq!(ocr.calls(), ["0x00000001"]);
+        assert!(output.windows[0].changed);
+        assert_eq!(output.changes_since_last, ["0x00000001"]);
+    }
+
+    #[test]
+    fn test_inspect_damage_skips_clean_windows() {
+        let dir = fake::scratch_dir("damage");
+        let mut desktop = fake::two_window_desktop();
+        let (_, state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+        desktop.captures.clear();
+
+        // The dialog was repainted; the terminal was not even captured.
+        desktop.paint("0x00000002", fake::solid(400, 200, [200, 200, 200]));
+        let damaged = HashSet::from(["0x00000002".to_string()]);
+        let ocr = fake::fixture_ocr();
+        let (output, new_state) =
+            inspect(&mut desktop, &ocr, &state, &options(), &dir, Some(&damaged));
+        assert_eq!(desktop.captures, ["0x00000002"]);
+        assert_eq!(output.changes_since_last, ["0x00000002"]);
+        assert_eq!(new_state.windows["0x00000001"], state.windows["0x00000001"]);
+    }
+
+    #[test]
+    fn test_inspect_screen_mode_attributes_text() {
+        let dir = fake::scratch_dir("screen");
+        let mut desktop = fake::two_window_desktop();
+        // One OCR of the whole screen: a bar along the bottom edge, a prompt
+        // in the terminal and a button in the dialog.
+        let header = include_str!("../fixtures/terminal.tsv")
+            .lines()
+            .next()
+            .unwrap();
+        let tsv = format!(
+            "{header}\n\
+             5\t1\t1\t1\t1\t1\t4\t10\t60\t14\t95.0\t$\n\
+             5\t1\t2\t1\t1\t1\t920\t450\t56\t16\t93.0\tCancel\n\
+             5\t1\t3\t1\t1\t1\t1200\t1004\t40\t14\t90.0\t12:00\n"
+        );
+        let ocr = fake::FakeOcr::default().with("screen", &tsv);
+        let screen = Options {
+            screen: true,
+            ..options()
+        };
+        let (output, state) = run(&mut desktop, &ocr, &PreviousState::default(), &screen, &dir);
+        assert_eq!(ocr.calls(), ["screen"]);
+        assert!(desktop.captures.is_empty());
+        assert_eq!(output.windows[0].ocr_text, "$");
+        let cancel = &output.windows[1].elements[0];
+        assert_eq!(
+            (cancel.text.as_str(), cancel.x, cancel.y),
+            ("Cancel", 920, 450)
+        );
+        assert_eq!(output.unattributed[0].text, "12:00");
+        assert!(output.windows.iter().all(|w| w.changed));
+
+        // An identical screen is neither captured per window nor re-OCR'd.
+        let ocr = fake::FakeOcr::default();
+        let (output, _) = run(&mut desktop, &ocr, &state, &screen, &dir);
+        assert!(ocr.calls().is_empty());
+        assert!(output.windows.iter().all(|w| w.cached && !w.changed));
+        assert_eq!(output.windows[1].elements[0].text, "Cancel");
+    }
+
+    #[test]
+    fn test_parse_i3_commands_in_order() {
+        let args = [
+            "--i3-command",
+            "workspace 2",
+            "--i3",
+            "--i3-command",
+            "layout splith",
+        ];
+        let options = with_flags(&args).unwrap();
+        assert!(options.i3);
+        assert_eq!(options.i3_commands, ["workspace 2", "layout splith"]);
+    }
+
+    #[test]
+    fn test_parse_args_uses_the_given_env() {
+        // A daemon parses a query with the client's variables, not its own.
+        let dir = fake::scratch_dir("client-env");
+        let config = dir.join("lg-inspect.json");
+        fs::write(&config, r#"{"ocr": {"languages": "deu"}}"#).unwrap();
+        let env: Env = [
+            ("LG_INSPECT_CONFIG", config.to_string_lossy().as_ref()),
+            ("LG_INSPECT_CHANGE_MODE", "phash"),
+            ("LG_INSPECT_PSM", "11"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        let options = parse_args(["--oem", "1"].map(String::from), &env).unwrap();
+        assert_eq!(options.change.mode, ChangeMode::Perceptual);
+        let settings = &options.ocr.settings;
+        assert_eq!(settings.languages, "deu");
+        assert_eq!((settings.psm, settings.oem), (Some(11), Some(1)));
+
+        let missing = [("LG_INSPECT_CONFIG".to_string(), "/nonexistent".to_string())];
+        assert!(parse_args([], &missing.into_iter().collect()).is_err());
+    }
+
+    #[test]
+    fn test_screen_and_diff_are_exclusive() {
+        assert!(with_flags(&["--screen", "--diff"]).is_err());
+    }
+
+    #[test]
+    fn test_inspect_marks_occluded_elements() {
+        use occlusion::Visibility;
+
+        let dir = fake::scratch_dir("occlusion");
+        let mut desktop = fake::two_window_desktop();
+        // The dialog covers the right part of the terminal's first lines.
+        desktop.windows[1].geometry.x = 80;
+        desktop.windows[1].geometry.y = 0;
+        let (output, _) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+        let terminal = &output.windows[0];
+        assert_eq!(terminal.z_index, Some(0));
+        assert_eq!(terminal.visibility, Visibility::PartiallyOccluded);
+        let visibility = |text: &str| {
+            terminal
+                .elements
+                .iter()
+                .find(|e| e.text == text)
+                .unwrap()
+                .visibility
+        };
+        assert_eq!(visibility("Documents"), Visibility::Visible);
+        assert_eq!(visibility("user@host:~$"), Visibility::PartiallyOccluded);
+        assert_eq!(visibility("ls"), Visibility::Hidden);
+        assert_eq!(output.windows[1].z_index, Some(1));
+
+        let json = serde_json::to_value(&output).unwrap();
+        assert_eq!(json["windows"][0]["elements"][3]["visibility"], "hidden");
+        // Visible is the default and left out.
+        assert!(json["windows"][1].get("visibility").is_none());
+    }
+
+    #[test]
+    fn test_inspect_reports_window_metadata() {
+        let dir = fake::scratch_dir("metadata");
+        let mut desktop = fake::two_window_desktop();
+        desktop.windows[1].meta = WindowMeta {
+            class: "Gedit".to_string(),
+            instance: "gedit".to_string(),
+            pid: Some(4242),
+            window_type: Some("dialog".to_string()),
+            state: vec!["modal".to_string()],
+            transient_for: Some("0x00000001".to_string()),
+        };
+        let (output, _) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+        let json = serde_json::to_value(&output).unwrap();
+        let dialog = &json["windows"][1];
+        assert_eq!(dialog["class"], "Gedit");
+        assert_eq!(dialog["window_type"], "dialog");
+        assert_eq!(dialog["state"], serde_json::json!(["modal"]));
+        assert_eq!(dialog["transient_for"], "0x00000001");
+        assert_eq!(dialog["desktop"], 0);
+        // Unset properties are left out.
+        assert!(json["windows"][0].get("class").is_none());
+        assert!(json["windows"][0].get("state").is_none());
+    }
+
+    #[test]
+    fn test_inspect_filter_skips_capture_and_keeps_state() {
+        let dir = fake::scratch_dir("filter");
+        let mut desktop = fake::two_window_desktop();
+        let (_, state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &PreviousState::default(),
+            &options(),
+            &dir,
+        );
+
+        desktop.captures.clear();
+        let terminal_only = with_flags(&["--window", "^Term"]).unwrap();
+        let (output, filtered_state) = run(
+            &mut desktop,
+            &fake::fixture_ocr(),
+            &state,
+            &terminal_only,
+            &dir,
+        );
+        assert_eq!(desktop.captures, ["0x00000001"]);
+        assert_eq!(output.windows.len(), 1);
+        assert_eq!(output.left_out, ["0x00000002"]);
+        assert_eq!(
+            filtered_state.windows["0x00000002"],
+            state.windows["0x00000002"]
+        );
+
+        // Back in view, the dialog is still known and unchanged.
+        let ocr = fake::fixture_ocr();
+        let (output, _) = run(&mut desktop, &ocr, &filtered_state, &options(), &dir);
+        assert!(output.changes_since_last.is_empty());
+        assert!(output.windows[1].cached);
+        assert!(ocr.calls().is_empty());
+    }
+
+    #[test]
+    fn test_inspect_parallel_ocr_keeps_window_order() {
+        let dir = fake::scratch_dir("parallel");
+        let mut desktop = fake::two_window_desktop();
+        let mut ocr = fake::fixture_ocr();
+        for n in 3..=6 {
+            let id = format!("0x0000000{n}");
+            let geometry = Geometry {
+                x: n * 100,
+                y: 600,
+                w: 90,
+                h: 90,
+            };
+            desktop = desktop.window(&id, &format!("Window {n}"), geometry, [0, 0, 0]);
+            ocr = ocr.with(&id, include_str!("../fixtures/terminal.tsv"));
+        }
+        let parallel = with_flags(&["--jobs", "3"]).unwrap();
+        let (output, _) = run(
+            &mut desktop,
+            &ocr,
+            &PreviousState::default(),
+            &parallel,
+            &dir,
+        );
+
+        let ids: Vec<&str> = output.windows.iter().map(|w| w.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            [
+                "0x00000001",
+                "0x00000002",
+                "0x00000003",
+                "0x00000004",
+                "0x00000005",
+                "0x00000006"
+            ]
+        );
+        assert_eq!(output.changes_since_last, ids);
+        let mut calls = ocr.calls();
+        calls.sort();
+        assert_eq!(calls, ids);
+        assert_eq!(output.windows[1].elements.len(), 6);
+        assert!(with_flags(&["--jobs", "0"]).is_err());
+    }
 }
@@ -0,0 +1,165 @@
+//! `--layout`: tesseract's page structure (blocks, paragraphs, lines, words).
+//!
+//! Design decision: `elements` is flat, which loses what tesseract knows
+//! about how text hangs together: which lines form a paragraph of a dialog's
+//! message, and which block is the button row. The tree is rebuilt from the
+//! stored word elements and each word's (block, paragraph, line), the same
+//! key the text and `--granularity` use, rather than from the TSV's own
+//! level 2-4 rows: the words are what the state file and OCR cache keep, so
+//! unchanged windows get their tree without re-running tesseract. A node's
+//! box is the union of its words, which is how tesseract computes it too.
+//!
+//! Results without tesseract's layout (re-read band by band, or split from a
+//! `--screen` OCR) get one block per row of words, as in `granularity`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::ocr::{self, LineKey};
+use crate::{Geometry, TextElement};
+
+#[derive(Serialize, Deserialize)]
+pub struct Block {
+    #[serde(flatten)]
+    pub bounds: Geometry,
+    pub paragraphs: Vec<Paragraph>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Paragraph {
+    #[serde(flatten)]
+    pub bounds: Geometry,
+    pub lines: Vec<Line>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Line {
+    #[serde(flatten)]
+    pub bounds: Geometry,
+    /// Left to right.
+    pub words: Vec<TextElement>,
+}
+
+/// Build the tree of word `elements`, whose lines are `lines` (or unknown
+/// when empty), in tesseract's reading order.
+#[allow(clippy::type_complexity)] // numbered nodes, only while building
+pub fn tree(elements: &[TextElement], lines: &[LineKey]) -> Vec<Block> {
+    let keyed: Vec<(LineKey, Vec<usize>)> = if lines.len() == elements.len() {
+        let mut keyed: Vec<(LineKey, Vec<usize>)> = Vec::new();
+        for (i, key) in lines.iter().enumerate() {
+            match keyed.iter_mut().find(|(k, _)| k == key) {
+                Some((_, line)) => line.push(i),
+                None => keyed.push((*key, vec![i])),
+            }
+        }
+        keyed
+    } else {
+        (0..)
+            .zip(ocr::rows(elements))
+            .map(|(row, line)| ((row, 1, 1), line))
+            .collect()
+    };
+
+    // Block and paragraph numbers, until the boxes are known.
+    let mut blocks: Vec<(u32, Vec<(u32, Vec<Line>)>)> = Vec::new();
+    for ((block, par, _), mut line) in keyed {
+        line.sort_by_key(|&i| elements[i].x);
+        let words: Vec<TextElement> = line.iter().map(|&i| elements[i].clone()).collect();
+        let line = Line {
+            bounds: union(words.iter().map(bounds_of)),
+            words,
+        };
+        if blocks.last().is_none_or(|(b, _)| *b != block) {
+            blocks.push((block, Vec::new()));
+        }
+        let paragraphs = &mut blocks.last_mut().expect("pushed above").1;
+        match paragraphs.last_mut() {
+            Some((p, lines)) if *p == par => lines.push(line),
+            _ => paragraphs.push((par, vec![line])),
+        }
+    }
+    blocks
+        .into_iter()
+        .map(|(_, paragraphs)| {
+            let paragraphs: Vec<Paragraph> = paragraphs
+                .into_iter()
+                .map(|(_, lines)| Paragraph {
+                    bounds: union(lines.iter().map(|l| l.bounds)),
+                    lines,
+                })
+                .collect();
+            Block {
+                bounds: union(paragraphs.iter().map(|p| p.bounds)),
+                paragraphs,
+            }
+        })
+        .collect()
+}
+
+/// Every word of the tree, for annotating them in place.
+pub fn words_mut(blocks: &mut [Block]) -> impl Iterator<Item = &mut TextElement> {
+    blocks
+        .iter_mut()
+        .flat_map(|b| &mut b.paragraphs)
+        .flat_map(|p| &mut p.lines)
+        .flat_map(|l| &mut l.words)
+}
+
+fn bounds_of(e: &TextElement) -> Geometry {
+    Geometry {
+        x: e.x,
+        y: e.y,
+        w: e.w,
+        h: e.h,
+    }
+}
+
+fn union(boxes: impl Iterator<Item = Geometry>) -> Geometry {
+    let mut edges: Option<[i64; 4]> = None;
+    for g in boxes {
+        let [x0, y0] = [i64::from(g.x), i64::from(g.y)];
+        let [x1, y1] = [x0 + i64::from(g.w), y0 + i64::from(g.h)];
+        edges = Some(match edges {
+            None => [x0, y0, x1, y1],
+            Some([a, b, c, d]) => [a.min(x0), b.min(y0), c.max(x1), d.max(y1)],
+        });
+    }
+    let [x0, y0, x1, y1] = edges.unwrap_or_default();
+    Geometry {
+        x: i32::try_from(x0).unwrap_or(0),
+        y: i32::try_from(y0).unwrap_or(0),
+        w: u32::try_from(x1 - x0).unwrap_or(0),
+        h: u32::try_from(y1 - y0).unwrap_or(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree_keeps_paragraphs_with_equal_line_numbers_apart() {
+        let origin = Geometry {
+            x: 100,
+            y: 50,
+            w: 400,
+            h: 200,
+        };
+        let result = ocr::parse_tsv(include_str!("../fixtures/settings.tsv"), &origin, 40.0);
+        let blocks = tree(&result.elements, &result.lines);
+
+        assert_eq!(blocks.len(), 2);
+        let settings = &blocks[0];
+        // Both paragraphs start at line 1.
+        assert_eq!(settings.paragraphs.len(), 2);
+        let heading = &settings.paragraphs[0].lines[0];
+        let texts: Vec<&str> = heading.words.iter().map(|w| w.text.as_str()).collect();
+        assert_eq!(texts, ["Display", "settings"]);
+        assert_eq!(settings.paragraphs[1].lines.len(), 2);
+        let b = settings.bounds;
+        assert_eq!((b.x, b.y, b.w, b.h), (100 + 20, 50 + 20, 226, 72));
+        assert_eq!(blocks[1].paragraphs[0].lines[0].words.len(), 2);
+
+        // Without the layout, every row is a block.
+        assert_eq!(tree(&result.elements, &[]).len(), 4);
+    }
+}
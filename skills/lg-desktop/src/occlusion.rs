@@ -0,0 +1,206 @@
+//! Which windows and elements are actually visible, given the stacking order.
+//!
+//! Design decision: a window's geometry says where it is, not whether it can
+//! be clicked there; an element under a dialog is still reported at its
+//! coordinates and the click lands on the dialog. Each window gets its
+//! position in `_NET_CLIENT_LIST_STACKING` (popups above all clients) as a
+//! z-index, and each window and element is compared against the rectangles
+//! of everything stacked above it. Rectangles are enough: X windows without
+//! the shape extension are rectangular, and i3 draws no shaped frames.
+//!
+//! Coverage is computed exactly by subtracting the occluders' rectangles, so
+//! an element peeking out between two windows is `partially_occluded`, not
+//! `hidden`.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{layout, Geometry, ListedWindow, WindowInfo};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    #[default]
+    Visible,
+    PartiallyOccluded,
+    Hidden,
+}
+
+impl Visibility {
+    /// The default, omitted from the output to save tokens.
+    #[allow(clippy::trivially_copy_pass_by_ref)] // serde's skip_serializing_if passes &T
+    pub fn is_visible(&self) -> bool {
+        *self == Self::Visible
+    }
+}
+
+/// Half-open rectangle `[x0, x1) x [y0, y1)` in desktop coordinates.
+#[derive(Clone, Copy)]
+struct Rect {
+    x0: i64,
+    y0: i64,
+    x1: i64,
+    y1: i64,
+}
+
+impl Rect {
+    fn new(x: i32, y: i32, w: u32, h: u32) -> Self {
+        Self {
+            x0: i64::from(x),
+            y0: i64::from(y),
+            x1: i64::from(x) + i64::from(w),
+            y1: i64::from(y) + i64::from(h),
+        }
+    }
+
+    fn of(g: &Geometry) -> Self {
+        Self::new(g.x, g.y, g.w, g.h)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.x1 <= self.x0 || self.y1 <= self.y0
+    }
+
+    /// The parts of `self` outside `other`, as up to four rectangles.
+    fn minus(self, other: &Rect) -> Vec<Rect> {
+        let overlap = Rect {
+            x0: self.x0.max(other.x0),
+            y0: self.y0.max(other.y0),
+            x1: self.x1.min(other.x1),
+            y1: self.y1.min(other.y1),
+        };
+        if overlap.is_empty() {
+            return vec![self];
+        }
+        [
+            // Above and below the overlap, full width.
+            Rect {
+                y1: overlap.y0,
+                ..self
+            },
+            Rect {
+                y0: overlap.y1,
+                ..self
+            },
+            // Left and right of it, overlap height.
+            Rect {
+                x1: overlap.x0,
+                y0: overlap.y0,
+                y1: overlap.y1,
+                ..self
+            },
+            Rect {
+                x0: overlap.x1,
+                y0: overlap.y0,
+                y1: overlap.y1,
+                ..self
+            },
+        ]
+        .into_iter()
+        .filter(|r| !r.is_empty())
+        .collect()
+    }
+}
+
+/// How much of `target` is left uncovered by `occluders`.
+fn visibility(target: Rect, occluders: &[Rect]) -> Visibility {
+    if target.is_empty() {
+        return Visibility::Visible;
+    }
+    let mut left = vec![target];
+    for occluder in occluders {
+        left = left.into_iter().flat_map(|r| r.minus(occluder)).collect();
+        if left.is_empty() {
+            return Visibility::Hidden;
+        }
+    }
+    match left.as_slice() {
+        [only]
+            if (only.x0, only.y0, only.x1, only.y1)
+                == (target.x0, target.y0, target.x1, target.y1) =>
+        {
+            Visibility::Visible
+        }
+        _ => Visibility::PartiallyOccluded,
+    }
+}
+
+/// Set `z_index` and `visibility` on every window and element (including
+/// `--layout` words). `listed` are all windows and popups, reported or not,
+/// and `stacking` lists the ids on screen bottom to top; windows missing
+/// from it are not on screen and are hidden. Does nothing when the stacking
+/// order is unknown.
+pub fn annotate(
+    windows: &mut [WindowInfo],
+    popups: &mut [WindowInfo],
+    listed: &[&ListedWindow],
+    stacking: &[String],
+) {
+    if stacking.is_empty() {
+        return;
+    }
+    let z_of: HashMap<&str, usize> = stacking
+        .iter()
+        .enumerate()
+        .map(|(z, id)| (id.as_str(), z))
+        .collect();
+    // Geometry of each stacked window, by z.
+    let mut stacked: Vec<(usize, Rect)> = listed
+        .iter()
+        .filter_map(|w| Some((*z_of.get(w.id.as_str())?, Rect::of(&w.geometry))))
+        .collect();
+    stacked.sort_by_key(|(z, _)| *z);
+
+    for window in windows.iter_mut().chain(popups.iter_mut()) {
+        window.z_index = z_of.get(window.id.as_str()).copied();
+        let occluders: Vec<Rect> = match window.z_index {
+            Some(z) => stacked
+                .iter()
+                .filter(|(above, _)| *above > z)
+                .map(|(_, rect)| *rect)
+                .collect(),
+            // Not on screen: covered by everything, whatever that is.
+            None => vec![Rect::of(&window.geometry)],
+        };
+        window.visibility = visibility(Rect::of(&window.geometry), &occluders);
+        let elements = window
+            .elements
+            .iter_mut()
+            .chain(window.diff.iter_mut().flat_map(|d| {
+                d.added
+                    .iter_mut()
+                    .chain(d.moved.iter_mut().map(|m| &mut m.element))
+            }))
+            .chain(layout::words_mut(&mut window.layout));
+        for e in elements {
+            e.visibility = visibility(Rect::new(e.x, e.y, e.w, e.h), &occluders);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_visibility_of_covered_rectangles() {
+        let target = Rect::new(0, 0, 100, 100);
+        assert_eq!(visibility(target, &[]), Visibility::Visible);
+        assert_eq!(
+            visibility(target, &[Rect::new(200, 0, 50, 50)]),
+            Visibility::Visible
+        );
+        assert_eq!(
+            visibility(target, &[Rect::new(50, 50, 100, 100)]),
+            Visibility::PartiallyOccluded
+        );
+        assert_eq!(
+            visibility(target, &[Rect::new(-10, -10, 200, 200)]),
+            Visibility::Hidden
+        );
+        // Two windows that together cover it, neither alone.
+        let halves = [Rect::new(0, 0, 60, 100), Rect::new(50, 0, 60, 100)];
+        assert_eq!(visibility(target, &halves), Visibility::Hidden);
+    }
+}
@@ -0,0 +1,582 @@
+//! OCR of window captures via tesseract TSV output.
+//!
+//! Design decision: the `tesseract` CLI loads the eng+jpn models on every
+//! call, which costs more than recognising a small window does. Builds with
+//! the `libtesseract` feature can instead keep the models loaded in-process
+//! ([`libtesseract::LibTesseract`]), which pays off most in the daemon, where
+//! one process serves every query. The feature is off by default because it
+//! needs the tesseract and leptonica development libraries at build time;
+//! the CLI stays the fallback and both produce the same TSV, so everything
+//! after [`OcrEngine::tsv`] is shared.
+
+#[cfg(feature = "libtesseract")]
+pub mod libtesseract;
+
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::occlusion::Visibility;
+use crate::preprocess::{self, Mode};
+use crate::{Geometry, OcrResult, TextElement};
+
+/// Something that turns a PNG on disk into tesseract TSV.
+///
+/// Keeping the engine to "image in, TSV out" lets tests serve canned TSV
+/// while the parsing below runs for real. Engines are shared by the OCR
+/// worker threads, hence `Sync`.
+pub trait OcrEngine: Sync {
+    fn tsv(&self, image_path: &str, settings: &OcrSettings) -> Result<String>;
+}
+
+/// How tesseract reads an image; see `config` for where it comes from.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OcrSettings {
+    /// Language models, `+`-separated as for `tesseract -l`.
+    pub languages: String,
+    /// Words below this confidence (0-100) are dropped.
+    pub min_confidence: f32,
+    /// Page segmentation mode (`tesseract --psm`); tesseract's own default
+    /// (3, fully automatic) when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub psm: Option<u8>,
+    /// OCR engine mode (`tesseract --oem`); tesseract's default when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oem: Option<u8>,
+    /// When to clean the image up first; see `preprocess`.
+    #[serde(default)]
+    pub preprocess: Mode,
+}
+
+impl Default for OcrSettings {
+    /// Design decision: confidence threshold is 40%. Lower catches more text
+    /// but adds noise tokens. Higher misses faint/small text. 40% was chosen
+    /// as a balance after testing with XFCE default theme -- most real UI
+    /// text scores >80%, while noise/artifacts score <30%.
+    fn default() -> Self {
+        Self {
+            languages: "eng+jpn".to_string(),
+            min_confidence: 40.0,
+            psm: None,
+            oem: None,
+            preprocess: Mode::Auto,
+        }
+    }
+}
+
+impl OcrSettings {
+    /// Identifies the settings in cache keys and stored results. Empty for
+    /// the defaults, so results stored before settings existed still match.
+    pub fn fingerprint(&self) -> String {
+        if *self == Self::default() {
+            return String::new();
+        }
+        let json = serde_json::to_string(self).unwrap_or_default();
+        format!("{:x}", Sha256::digest(json.as_bytes()))[..16].to_string()
+    }
+}
+
+/// An engine and the settings to run it with.
+#[derive(Clone, Copy)]
+pub struct Reader<'a> {
+    pub engine: &'a dyn OcrEngine,
+    pub settings: &'a OcrSettings,
+}
+
+impl Reader<'_> {
+    /// Run OCR and extract both full text and per-word bounding boxes.
+    ///
+    /// Uses `tesseract tsv` output format which gives word-level positions.
+    /// Element coordinates are relative to the window: `origin` is where the
+    /// capture (or the part of it being read) sits inside the window, and is
+    /// added to every box. Callers make them absolute with the window's
+    /// position when reporting.
+    ///
+    /// `image` is the capture saved at `image_path`. In `auto` preprocessing
+    /// mode, a capture that yields almost no words is read again
+    /// preprocessed, keeping whichever pass read more.
+    pub fn read(
+        &self,
+        image: &RgbaImage,
+        image_path: &str,
+        origin: &Geometry,
+    ) -> Result<OcrResult> {
+        let result = self.read_once(image, image_path, origin)?;
+        if self.settings.preprocess != Mode::Auto || result.elements.len() >= preprocess::MIN_WORDS
+        {
+            return Ok(result);
+        }
+        match self.read_prepared(image, image_path, origin) {
+            Ok(prepared)
+                if preprocess::score(&prepared.elements) > preprocess::score(&result.elements) =>
+            {
+                Ok(prepared)
+            }
+            Ok(_) => Ok(result),
+            Err(e) => {
+                eprintln!("[lg-inspect] warning: OCR of preprocessed {image_path} failed: {e:#}");
+                Ok(result)
+            }
+        }
+    }
+
+    /// One OCR pass: preprocessed in `always` mode, as captured otherwise.
+    pub fn read_once(
+        &self,
+        image: &RgbaImage,
+        image_path: &str,
+        origin: &Geometry,
+    ) -> Result<OcrResult> {
+        if self.settings.preprocess == Mode::Always {
+            return self.read_prepared(image, image_path, origin);
+        }
+        let tsv = self.engine.tsv(image_path, self.settings)?;
+        Ok(OcrResult {
+            settings: self.settings.fingerprint(),
+            ..parse_tsv(&tsv, origin, self.settings.min_confidence)
+        })
+    }
+
+    /// OCR `image` preprocessed, saved next to `image_path` as `*.pre.png`.
+    fn read_prepared(
+        &self,
+        image: &RgbaImage,
+        image_path: &str,
+        origin: &Geometry,
+    ) -> Result<OcrResult> {
+        let prepared = preprocess::prepare(image);
+        let path = Path::new(image_path).with_extension("pre.png");
+        prepared
+            .image
+            .save(&path)
+            .with_context(|| format!("failed to save {}", path.display()))?;
+        let tsv = self.engine.tsv(&path.to_string_lossy(), self.settings)?;
+        let scaled = Geometry {
+            x: 0,
+            y: 0,
+            w: prepared.image.width(),
+            h: prepared.image.height(),
+        };
+        let result = parse_tsv(&tsv, &scaled, self.settings.min_confidence);
+        Ok(OcrResult {
+            elements: preprocess::unscale(result.elements, prepared.scale, origin.x, origin.y),
+            preprocessed: true,
+            settings: self.settings.fingerprint(),
+            ..result
+        })
+    }
+}
+
+/// Column names of tesseract's TSV output, which [`parse_tsv`] skips.
+#[cfg_attr(not(feature = "libtesseract"), allow(dead_code))]
+pub const TSV_HEADER: &str =
+    "level\tpage_num\tblock_num\tpar_num\tline_num\tword_num\tleft\ttop\twidth\theight\tconf\ttext\n";
+
+/// Which engine the user asked for via `--ocr-engine` /
+/// `LG_INSPECT_OCR_ENGINE`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum OcrChoice {
+    /// libtesseract if built in and it initialises, else the CLI.
+    #[default]
+    Auto,
+    Cli,
+    Lib,
+}
+
+impl OcrChoice {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "auto" => Some(Self::Auto),
+            "cli" => Some(Self::Cli),
+            "lib" => Some(Self::Lib),
+            _ => None,
+        }
+    }
+}
+
+/// Set up the engine for `choice`. Engines that keep models loaded load
+/// those of `settings`, which most windows are read with, up front.
+#[cfg_attr(not(feature = "libtesseract"), allow(unused_variables))]
+pub fn engine(choice: OcrChoice, settings: &OcrSettings) -> Result<Box<dyn OcrEngine>> {
+    match choice {
+        OcrChoice::Cli => Ok(Box::new(TesseractCli)),
+        #[cfg(feature = "libtesseract")]
+        OcrChoice::Lib => Ok(Box::new(libtesseract::LibTesseract::new(settings)?)),
+        #[cfg(not(feature = "libtesseract"))]
+        OcrChoice::Lib => bail!("lg-inspect was built without the libtesseract feature"),
+        #[cfg(feature = "libtesseract")]
+        OcrChoice::Auto => match libtesseract::LibTesseract::new(settings) {
+            Ok(engine) => Ok(Box::new(engine)),
+            Err(e) => {
+                eprintln!(
+                    "[lg-inspect] warning: libtesseract unavailable, using the tesseract CLI: {e:#}"
+                );
+                Ok(Box::new(TesseractCli))
+            }
+        },
+        #[cfg(not(feature = "libtesseract"))]
+        OcrChoice::Auto => Ok(Box::new(TesseractCli)),
+    }
+}
+
+/// The `tesseract` command-line tool.
+pub struct TesseractCli;
+
+impl OcrEngine for TesseractCli {
+    fn tsv(&self, image_path: &str, settings: &OcrSettings) -> Result<String> {
+        let mut command = Command::new("tesseract");
+        command.args([image_path, "stdout", "-l", &settings.languages]);
+        if let Some(psm) = settings.psm {
+            command.args(["--psm", &psm.to_string()]);
+        }
+        if let Some(oem) = settings.oem {
+            command.args(["--oem", &oem.to_string()]);
+        }
+        let out = command
+            .arg("tsv")
+            .output()
+            .context("failed to run tesseract")?;
+        if !out.status.success() {
+            bail!(
+                "tesseract failed (exit {}): {}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+    }
+}
+
+/// Position of a word's line in tesseract's layout: (block, paragraph, line).
+/// Line numbers restart in every paragraph, so all three are needed.
+pub type LineKey = (u32, u32, u32);
+
+/// Parse tesseract TSV into line-joined text and word elements, with the
+/// line of each word, dropping words below `min_confidence` (see
+/// [`OcrSettings`]).
+pub fn parse_tsv(text: &str, window_geom: &Geometry, min_confidence: f32) -> OcrResult {
+    let mut words: Vec<(LineKey, TextElement)> = Vec::new();
+
+    for line in text.lines().skip(1) {
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() < 12 {
+            continue;
+        }
+        // TSV level 5 = word
+        let level: u32 = parts[0].parse().unwrap_or(0);
+        if level != 5 {
+            continue;
+        }
+        let key = (
+            parts[2].parse().unwrap_or(0),
+            parts[3].parse().unwrap_or(0),
+            parts[4].parse().unwrap_or(0),
+        );
+        let conf: f32 = parts[10].parse().unwrap_or(-1.0);
+        let word = parts[11].trim();
+
+        if word.is_empty() || conf < min_confidence {
+            continue;
+        }
+
+        let left: i32 = parts[6].parse().unwrap_or(0);
+        let top: i32 = parts[7].parse().unwrap_or(0);
+        let width: u32 = parts[8].parse().unwrap_or(0);
+        let height: u32 = parts[9].parse().unwrap_or(0);
+
+        words.push((
+            key,
+            TextElement {
+                text: word.to_string(),
+                x: window_geom.x + left,
+                y: window_geom.y + top,
+                w: width,
+                h: height,
+                confidence: conf,
+                visibility: Visibility::Visible,
+                id: None,
+            },
+        ));
+    }
+    let words = merge_cjk(words);
+
+    // Reconstruct text in tesseract's reading order, a line per line and a
+    // blank line between blocks
+    let mut lines: Vec<(LineKey, Vec<&str>)> = Vec::new();
+    for (key, e) in &words {
+        match lines.last_mut() {
+            Some((line, texts)) if line == key => texts.push(&e.text),
+            _ => lines.push((*key, vec![&e.text])),
+        }
+    }
+    let mut full_text = String::new();
+    for (n, (key, words)) in lines.iter().enumerate() {
+        if n > 0 {
+            let same_block = lines[n - 1].0 .0 == key.0;
+            full_text.push_str(if same_block { "\n" } else { "\n\n" });
+        }
+        full_text.push_str(&words.join(" "));
+    }
+
+    let (lines, elements) = words.into_iter().unzip();
+    OcrResult {
+        text: full_text,
+        elements,
+        lines,
+        ..OcrResult::default()
+    }
+}
+
+/// Whether `c` belongs to a script written without spaces between words:
+/// kana, CJK ideographs, CJK punctuation and fullwidth forms. Hangul is
+/// left out, as Korean separates words with spaces.
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3000}'..='\u{30FF}'
+        | '\u{31F0}'..='\u{31FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FF00}'..='\u{FFEF}')
+}
+
+/// Merge runs of CJK glyphs on the same line into one element.
+///
+/// Design decision: with `jpn` tesseract reports every character as its own
+/// "word", so a Japanese label became a dozen one-glyph elements and its
+/// text came out letter-spaced. Neighbours on one line are merged when the
+/// gap between them is narrower than a space would be: up to half the glyph
+/// height between two CJK characters (which are set edge to edge), and a
+/// fifth of it where CJK meets Latin, as in `保存(S)`. Wider gaps, such as
+/// between menu items or table columns, still separate elements. The merged
+/// element gets the union box and the lowest confidence of its glyphs.
+fn merge_cjk(words: Vec<(LineKey, TextElement)>) -> Vec<(LineKey, TextElement)> {
+    let mut merged: Vec<(LineKey, TextElement)> = Vec::with_capacity(words.len());
+    for (key, e) in words {
+        if let Some((last_key, last)) = merged.last_mut() {
+            if *last_key == key && joins(last, &e) {
+                extend(last, &e);
+                continue;
+            }
+        }
+        merged.push((key, e));
+    }
+    merged
+}
+
+fn joins(a: &TextElement, b: &TextElement) -> bool {
+    let cjk = [
+        a.text.chars().last().is_some_and(is_cjk),
+        b.text.chars().next().is_some_and(is_cjk),
+    ];
+    let glyph = i64::from(a.h.max(b.h));
+    let max_gap = match cjk {
+        [true, true] => glyph / 2,
+        [true, false] | [false, true] => glyph / 5,
+        [false, false] => return false,
+    };
+    let gap = i64::from(b.x) - (i64::from(a.x) + i64::from(a.w));
+    gap <= max_gap
+}
+
+fn extend(a: &mut TextElement, b: &TextElement) {
+    let right = |e: &TextElement| i64::from(e.x) + i64::from(e.w);
+    let bottom = |e: &TextElement| i64::from(e.y) + i64::from(e.h);
+    let (x1, y1) = (right(a).max(right(b)), bottom(a).max(bottom(b)));
+    a.x = a.x.min(b.x);
+    a.y = a.y.min(b.y);
+    a.w = u32::try_from(x1 - i64::from(a.x)).unwrap_or(0);
+    a.h = u32::try_from(y1 - i64::from(a.y)).unwrap_or(0);
+    a.text.push_str(&b.text);
+    a.confidence = a.confidence.min(b.confidence);
+}
+
+/// Group elements into rows by position, for results without tesseract's
+/// layout: words whose vertical centre falls within a row's first word form
+/// one row. Returns element indices, rows top to bottom, each left to right.
+pub fn rows(elements: &[TextElement]) -> Vec<Vec<usize>> {
+    let mut rows: Vec<(i64, i64, Vec<usize>)> = Vec::new();
+    for (i, e) in elements.iter().enumerate() {
+        let top = i64::from(e.y);
+        let centre = top + i64::from(e.h / 2);
+        match rows
+            .iter_mut()
+            .find(|(top, bottom, _)| (*top..*bottom).contains(&centre))
+        {
+            Some(row) => row.2.push(i),
+            None => rows.push((top, top + i64::from(e.h), vec![i])),
+        }
+    }
+    rows.sort_by_key(|row| row.0);
+    rows.into_iter()
+        .map(|(_, _, mut row)| {
+            row.sort_by_key(|&i| elements[i].x);
+            row
+        })
+        .collect()
+}
+
+/// Rebuild window text from elements, one line per row (see [`rows`]).
+pub fn text_from_elements(elements: &[TextElement]) -> String {
+    rows(elements)
+        .into_iter()
+        .map(|row| {
+            row.iter()
+                .map(|&i| elements[i].text.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tsv_terminal_fixture() {
+        let geom = Geometry {
+            x: 0,
+            y: 0,
+            w: 640,
+            h: 480,
+        };
+        let OcrResult { text, elements, .. } =
+            parse_tsv(include_str!("../fixtures/terminal.tsv"), &geom, 40.0);
+        assert_eq!(text, "user@host:~$ ls\nDocuments Downloads");
+        assert_eq!(elements.len(), 4);
+    }
+
+    #[test]
+    fn test_tsv_header_matches_cli_output() {
+        // libtesseract output gets this header prepended; it must be the
+        // line the CLI writes, or the first word would be skipped instead.
+        let cli = include_str!("../fixtures/terminal.tsv");
+        assert_eq!(cli.lines().next(), TSV_HEADER.lines().next());
+        assert_eq!(OcrChoice::parse("lib"), Some(OcrChoice::Lib));
+        assert_eq!(OcrChoice::parse("tesseract"), None);
+    }
+
+    #[test]
+    fn test_parse_tsv_merges_cjk_glyphs() {
+        let geom = Geometry {
+            x: 100,
+            y: 50,
+            w: 480,
+            h: 160,
+        };
+        let OcrResult { text, elements, .. } =
+            parse_tsv(include_str!("../fixtures/japanese-dialog.tsv"), &geom, 40.0);
+        assert_eq!(
+            text,
+            "名前を付けて保存\n\nファイル名： report.txt\n\n保存(S) キャンセル"
+        );
+        let texts: Vec<&str> = elements.iter().map(|e| e.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                "名前を付けて保存",
+                "ファイル名：",
+                "report.txt",
+                "保存(S)",
+                "キャンセル"
+            ]
+        );
+        let title = &elements[0];
+        assert_eq!(
+            (title.x, title.y, title.w, title.h),
+            (100 + 20, 50 + 12, 127, 16)
+        );
+        // The least certain glyph: け at 90.2.
+        assert!((title.confidence - 90.2).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_cjk_merge_keeps_wide_gaps_and_lines_apart() {
+        let glyph = |text: &str, x: i32| TextElement {
+            text: text.to_string(),
+            x,
+            y: 0,
+            w: 15,
+            h: 16,
+            confidence: 90.0,
+            visibility: Visibility::Visible,
+            id: None,
+        };
+        let merged = merge_cjk(vec![
+            ((1, 1, 1), glyph("編", 0)),
+            ((1, 1, 1), glyph("集", 16)),
+            // Next menu item, a space's width away.
+            ((1, 1, 1), glyph("表", 45)),
+            // Same line number, next paragraph.
+            ((1, 2, 1), glyph("示", 61)),
+            ((1, 2, 1), glyph("OK", 90)),
+        ]);
+        let texts: Vec<&str> = merged.iter().map(|(_, e)| e.text.as_str()).collect();
+        assert_eq!(texts, ["編集", "表", "示", "OK"]);
+        assert!(is_cjk('ー') && is_cjk('漢') && is_cjk('Ｆ'));
+        assert!(!is_cjk('a') && !is_cjk('한'));
+    }
+
+    #[test]
+    fn test_parse_tsv_text_in_reading_order() {
+        let geom = Geometry {
+            x: 0,
+            y: 0,
+            w: 400,
+            h: 200,
+        };
+        // Line numbers restart per paragraph: the heading and the first
+        // option are both line 1, but separate lines.
+        let result = parse_tsv(include_str!("../fixtures/settings.tsv"), &geom, 40.0);
+        assert_eq!(
+            result.text,
+            "Display settings\nShow hidden files\nUse dark theme for all windows\n\nCancel Apply"
+        );
+        assert_eq!(result.lines[0], (1, 1, 1));
+        assert_eq!(result.lines[2], (1, 2, 1));
+    }
+
+    #[test]
+    fn test_parse_tsv_absolute_coordinates() {
+        let geom = Geometry {
+            x: 100,
+            y: 50,
+            w: 400,
+            h: 200,
+        };
+        let elements = parse_tsv(include_str!("../fixtures/dialog.tsv"), &geom, 40.0).elements;
+        assert_eq!(elements.len(), 6);
+        let save = elements
+            .iter()
+            .find(|e| e.text == "Save" && e.y > 100)
+            .unwrap();
+        assert_eq!(
+            (save.x, save.y, save.w, save.h),
+            (100 + 300, 50 + 150, 40, 16)
+        );
+    }
+
+    #[test]
+    fn test_parse_tsv_drops_low_confidence() {
+        let tsv = "level\tpage_num\tblock_num\tpar_num\tline_num\tword_num\tleft\ttop\twidth\theight\tconf\ttext\n\
+                   5\t1\t1\t1\t1\t1\t0\t0\t10\t10\t12.5\t~\n\
+                   5\t1\t1\t1\t1\t2\t20\t0\t30\t10\t91.0\tFile\n";
+        let geom = Geometry {
+            x: 0,
+            y: 0,
+            w: 100,
+            h: 100,
+        };
+        let OcrResult { text, elements, .. } = parse_tsv(tsv, &geom, 40.0);
+        assert_eq!(text, "File");
+        assert_eq!(elements.len(), 1);
+        // A stricter cutoff from the settings.
+        assert!(parse_tsv(tsv, &geom, 95.0).elements.is_empty());
+    }
+}
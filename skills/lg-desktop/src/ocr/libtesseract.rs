@@ -0,0 +1,82 @@
+//! In-process OCR through libtesseract (cargo feature `libtesseract`).
+
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use tesseract::{OcrEngineMode, Tesseract};
+
+use super::{OcrEngine, OcrSettings, TSV_HEADER};
+
+/// Languages and engine mode, fixed when an instance is initialised.
+type Models = (String, Option<u8>);
+
+/// Initialised tesseract instances, kept between calls so the language
+/// models are loaded once per process instead of once per image.
+///
+/// A `TessBaseAPI` handles one image at a time, so each OCR worker takes an
+/// idle instance with the models it needs (initialising another if all are
+/// busy) and puts it back when done; the pool grows to the number of
+/// concurrent workers per set of models.
+pub struct LibTesseract {
+    idle: Mutex<Vec<(Models, Tesseract)>>,
+}
+
+impl LibTesseract {
+    /// Load the models of `settings` up front, so a missing language pack
+    /// fails here rather than on the first window.
+    pub fn new(settings: &OcrSettings) -> Result<Self> {
+        let models = models(settings);
+        let api = init(&models)?;
+        Ok(Self {
+            idle: Mutex::new(vec![(models, api)]),
+        })
+    }
+}
+
+fn models(settings: &OcrSettings) -> Models {
+    (settings.languages.clone(), settings.oem)
+}
+
+fn init((languages, oem): &Models) -> Result<Tesseract> {
+    let mode = match oem {
+        Some(0) => OcrEngineMode::TesseractOnly,
+        Some(1) => OcrEngineMode::LstmOnly,
+        Some(2) => OcrEngineMode::TesseractLstmCombined,
+        _ => OcrEngineMode::Default,
+    };
+    Tesseract::new_with_oem(None, Some(languages), mode)
+        .with_context(|| format!("failed to initialise libtesseract with {languages}"))
+}
+
+impl OcrEngine for LibTesseract {
+    fn tsv(&self, image_path: &str, settings: &OcrSettings) -> Result<String> {
+        let models = models(settings);
+        let idle = {
+            let mut idle = self.idle.lock().unwrap();
+            let found = idle.iter().position(|(m, _)| *m == models);
+            found.map(|i| idle.swap_remove(i).1)
+        };
+        let api = match idle {
+            Some(api) => api,
+            None => init(&models)?,
+        };
+        // The builder methods consume the instance; on error it is dropped
+        // and the next call initialises a fresh one. The page segmentation
+        // mode is per image, so it is set on every call (3 is tesseract's
+        // default).
+        let psm = settings.psm.unwrap_or(3).to_string();
+        let mut api = api
+            .set_variable("tessedit_pageseg_mode", &psm)
+            .context("libtesseract rejected the page segmentation mode")?
+            .set_image(image_path)
+            .with_context(|| format!("libtesseract could not read {image_path}"))?
+            .recognize()
+            .context("libtesseract recognition failed")?;
+        let body = api
+            .get_tsv_text(0)
+            .context("libtesseract produced no TSV")?;
+        self.idle.lock().unwrap().push((models, api));
+        // Unlike the CLI's TSV renderer, the API leaves out the header row.
+        Ok(format!("{TSV_HEADER}{body}"))
+    }
+}
@@ -0,0 +1,160 @@
+//! Image clean-up before OCR, for windows tesseract reads poorly as captured.
+//!
+//! Design decision: tesseract is trained on dark text on a light page at
+//! print resolution. Terminals break all three assumptions (light text on a
+//! dark background, small bitmap fonts, coloured prompts) and often come
+//! back with no text at all. The pipeline turns a capture into what
+//! tesseract expects: grayscale, inverted when the background is dark,
+//! upscaled, sharpened and binarized against the local mean brightness so
+//! coloured text and gradients do not wash out under a single threshold.
+//! It costs a second OCR, so by default (`auto`) it only runs when the plain
+//! OCR found almost nothing, and the result with more text wins; `always`
+//! skips the plain pass for windows known to need it, and `off` never
+//! preprocesses. Elements are mapped back to capture coordinates, so
+//! preprocessing never shows in the boxes.
+
+use image::imageops::{self, FilterType};
+use image::{GrayImage, Luma, RgbaImage};
+use imageproc::filter::sharpen3x3;
+use imageproc::integral_image::{integral_image, sum_image_pixels};
+use serde::{Deserialize, Serialize};
+
+use crate::TextElement;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    Off,
+    #[default]
+    Auto,
+    Always,
+}
+
+impl Mode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(Self::Off),
+            "auto" => Some(Self::Auto),
+            "always" => Some(Self::Always),
+            _ => None,
+        }
+    }
+}
+
+/// In `auto` mode, a plain OCR with fewer words than this is retried.
+pub const MIN_WORDS: usize = 3;
+
+/// Upscale factor: 2x brings a 13px terminal font to the ~20px x-height
+/// range tesseract's models were trained on.
+const SCALE: u32 = 2;
+
+/// Larger images are not upscaled, as OCR time grows with the area.
+const MAX_SCALED_PIXELS: u64 = 8_000_000;
+
+/// Half the side of the neighbourhood whose mean a pixel is compared to,
+/// before scaling; a few text lines high.
+const BLOCK_RADIUS: u32 = 12;
+
+/// How much darker than its neighbourhood a pixel must be to count as ink,
+/// so anti-aliasing and gradients in empty areas stay white.
+const INK_CONTRAST: u32 = 12;
+
+/// A capture ready for OCR, `scale` times the original size.
+pub struct Prepared {
+    pub image: GrayImage,
+    pub scale: u32,
+}
+
+/// Run the whole pipeline on `image`.
+pub fn prepare(image: &RgbaImage) -> Prepared {
+    let mut gray = imageops::grayscale(image);
+    if is_dark(&gray) {
+        imageops::invert(&mut gray);
+    }
+    let (w, h) = gray.dimensions();
+    let scaled_pixels = u64::from(w) * u64::from(h) * u64::from(SCALE * SCALE);
+    let scale = if scaled_pixels <= MAX_SCALED_PIXELS {
+        SCALE
+    } else {
+        1
+    };
+    if scale > 1 {
+        gray = imageops::resize(&gray, w * scale, h * scale, FilterType::CatmullRom);
+    }
+    let sharp = sharpen3x3(&gray);
+    Prepared {
+        image: binarize(&sharp, BLOCK_RADIUS * scale),
+        scale,
+    }
+}
+
+/// Whether most pixels are darker than mid-gray, i.e. the background is.
+fn is_dark(image: &GrayImage) -> bool {
+    let dark = image.pixels().filter(|p| p[0] < 128).count();
+    dark * 2 > image.pixels().len()
+}
+
+/// Black where a pixel is clearly darker than the mean of the
+/// `2 * radius + 1` square around it, white elsewhere.
+fn binarize(image: &GrayImage, radius: u32) -> GrayImage {
+    let (w, h) = image.dimensions();
+    if w == 0 || h == 0 {
+        return image.clone();
+    }
+    let integral = integral_image::<_, u32>(image);
+    GrayImage::from_fn(w, h, |x, y| {
+        let (x0, y0) = (x.saturating_sub(radius), y.saturating_sub(radius));
+        let (x1, y1) = ((x + radius).min(w - 1), (y + radius).min(h - 1));
+        let count = (x1 - x0 + 1) * (y1 - y0 + 1);
+        let mean = sum_image_pixels(&integral, x0, y0, x1, y1)[0] / count;
+        let ink = u32::from(image.get_pixel(x, y)[0]) + INK_CONTRAST < mean;
+        Luma([if ink { 0 } else { 255 }])
+    })
+}
+
+/// Elements read from a prepared image, at `scale`, back in capture
+/// coordinates offset like the capture's.
+pub fn unscale(elements: Vec<TextElement>, scale: u32, dx: i32, dy: i32) -> Vec<TextElement> {
+    let scale_i = i32::try_from(scale).unwrap_or(1);
+    elements
+        .into_iter()
+        .map(|e| TextElement {
+            x: e.x.div_euclid(scale_i) + dx,
+            y: e.y.div_euclid(scale_i) + dy,
+            w: e.w.div_ceil(scale),
+            h: e.h.div_ceil(scale),
+            ..e
+        })
+        .collect()
+}
+
+/// How much a result read: its characters, so a pass finding more text
+/// beats one finding a few stray marks.
+pub fn score(elements: &[TextElement]) -> usize {
+    elements.iter().map(|e| e.text.chars().count()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_prepare_inverts_dark_terminal_and_upscales() {
+        // Light-gray "text" stroke on a near-black terminal background.
+        let mut image = RgbaImage::from_pixel(60, 20, Rgba([16, 16, 24, 255]));
+        for x in 10..30 {
+            for y in 8..11 {
+                image.put_pixel(x, y, Rgba([200, 200, 200, 255]));
+            }
+        }
+        let Prepared { image, scale } = prepare(&image);
+
+        assert_eq!(scale, 2);
+        assert_eq!(image.dimensions(), (120, 40));
+        // The stroke is now black ink on white paper.
+        assert_eq!(image.get_pixel(40, 19)[0], 0);
+        assert_eq!(image.get_pixel(100, 5)[0], 255);
+        assert_eq!(image.get_pixel(5, 35)[0], 255);
+    }
+}
@@ -0,0 +1,244 @@
+//! `--screen`: read the whole screen with one capture and one OCR, then
+//! attribute every element to the window it was read from.
+//!
+//! Design decision: per-window capture only sees managed clients and popups,
+//! so the i3 bar, desktop icons and anything else drawn outside the client
+//! list never reach the agent, and every changed window pays tesseract's
+//! start-up cost. In screen mode the root window is captured once and OCR'd
+//! once. Each element goes to the topmost window (in stacking order) whose
+//! rectangle contains the element's centre, which is exactly the window that
+//! drew those pixels; elements outside every window are reported as
+//! `unattributed`. Per-window change detection still runs, on each window's
+//! on-screen rectangle cropped from the same capture, so `changed` means
+//! "what the user sees changed", occluded parts included.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use image::{imageops, RgbaImage};
+
+use crate::backend::{clip_to_screen, Capture, CaptureError, DesktopBackend};
+use crate::cache::{self, OcrCache};
+use crate::change::content_hash;
+use crate::ocr::{self, Reader};
+use crate::{save_capture, Geometry, ListedWindow, OcrResult, PreviousState, TextElement};
+
+/// File stem of the saved screen capture.
+const SCREEN_ID: &str = "screen";
+
+/// One capture and OCR of the whole screen, split up by window.
+pub struct ScreenOcr {
+    image: RgbaImage,
+    /// Window ids on screen, bottom to top.
+    stacking: Vec<String>,
+    /// Absolute elements per window id.
+    by_window: HashMap<String, Vec<TextElement>>,
+    /// Elements outside every listed window (bars, desktop).
+    pub unattributed: Vec<TextElement>,
+    /// Why the screen could not be OCR'd, if it could not.
+    pub error: Option<String>,
+    /// The OCR was reused from an identical earlier screen.
+    pub cached: bool,
+    /// The screen is identical to the previous inspection's.
+    pub unchanged: bool,
+    /// Hash of the capture and its OCR, for the next run's cache.
+    pub hash: String,
+    pub result: Option<OcrResult>,
+}
+
+impl ScreenOcr {
+    /// Capture and OCR the screen, reusing the previous or a cached OCR
+    /// when the screen is pixel-identical and was read with the same
+    /// settings. `windows` are the listed clients and popups.
+    pub fn read(
+        backend: &mut dyn DesktopBackend,
+        reader: Reader,
+        cache: &OcrCache,
+        previous: &PreviousState,
+        windows: &[&ListedWindow],
+        stacking: &[String],
+        dir: &Path,
+    ) -> Result<Self, CaptureError> {
+        let image = backend.capture_screen()?;
+        let hash = content_hash(&image);
+
+        let unchanged = previous.screen.as_ref() == Some(&hash);
+        let key = cache::key(&hash, reader.settings);
+        let reused = unchanged
+            .then(|| previous.ocr_of(SCREEN_ID, &hash))
+            .flatten()
+            .filter(|r| r.settings == reader.settings.fingerprint())
+            .cloned()
+            .or_else(|| cache.get(&key));
+        let cached = reused.is_some();
+        let (result, error) = match reused {
+            Some(result) => (Some(result), None),
+            None => match read_fresh(reader, &image, dir) {
+                Ok(result) => {
+                    cache.put(&key, &result);
+                    (Some(result), None)
+                }
+                Err(e) => {
+                    eprintln!("[lg-inspect] warning: OCR failed for the screen: {e:#}");
+                    (None, Some(format!("{e:#}")))
+                }
+            },
+        };
+
+        let elements = result.as_ref().map_or(&[][..], |r| &r.elements[..]);
+        let (by_window, unattributed) = attribute(elements, stacking, windows);
+        Ok(Self {
+            image,
+            stacking: stacking.to_vec(),
+            by_window,
+            unattributed,
+            error,
+            cached,
+            unchanged,
+            hash,
+            result,
+        })
+    }
+
+    /// Record the screen's hash and OCR for the next run.
+    pub fn remember(&self, state: &mut PreviousState) {
+        if let Some(result) = &self.result {
+            state.remember_ocr(SCREEN_ID, &self.hash, result.clone());
+            state.screen = Some(self.hash.clone());
+        }
+    }
+
+    /// The on-screen part of `window`, cut from the screen capture. Windows
+    /// missing from the stacking order (other workspace, minimized) are not
+    /// on screen.
+    pub fn crop(&self, window: &ListedWindow) -> Result<Capture, CaptureError> {
+        if !self.stacking.contains(&window.id) {
+            return Err(CaptureError::Offscreen("not on screen".to_string()));
+        }
+        let g = window.geometry;
+        let (left, top, width, height) =
+            clip_to_screen(&g, self.image.width(), self.image.height())
+                .ok_or_else(|| CaptureError::Offscreen("outside the screen".to_string()))?;
+        let image = imageops::crop_imm(&self.image, left, top, width, height).to_image();
+        Ok(Capture::clipped(image, &g, left, top))
+    }
+
+    /// The elements attributed to `window`, window-relative like every
+    /// `OcrResult`.
+    pub fn window_result(&self, window: &ListedWindow) -> OcrResult {
+        let g = window.geometry;
+        let elements: Vec<TextElement> = self
+            .by_window
+            .get(&window.id)
+            .into_iter()
+            .flatten()
+            .map(|e| TextElement {
+                x: e.x - g.x,
+                y: e.y - g.y,
+                ..e.clone()
+            })
+            .collect();
+        OcrResult {
+            text: ocr::text_from_elements(&elements),
+            elements,
+            settings: self
+                .result
+                .as_ref()
+                .map(|r| r.settings.clone())
+                .unwrap_or_default(),
+            preprocessed: self.result.as_ref().is_some_and(|r| r.preprocessed),
+            ..OcrResult::default()
+        }
+    }
+}
+
+fn read_fresh(reader: Reader, image: &RgbaImage, dir: &Path) -> anyhow::Result<OcrResult> {
+    let path = save_capture(image, dir, SCREEN_ID)
+        .ok_or_else(|| anyhow::anyhow!("failed to save the screen capture"))?;
+    let origin = Geometry {
+        x: 0,
+        y: 0,
+        w: image.width(),
+        h: image.height(),
+    };
+    reader.read(image, &path, &origin)
+}
+
+/// Split absolute `elements` by the topmost window containing each one's
+/// centre; the rest are unattributed.
+fn attribute(
+    elements: &[TextElement],
+    stacking: &[String],
+    windows: &[&ListedWindow],
+) -> (HashMap<String, Vec<TextElement>>, Vec<TextElement>) {
+    let top_down: Vec<&ListedWindow> = stacking
+        .iter()
+        .rev()
+        .filter_map(|id| windows.iter().find(|w| w.id == *id).copied())
+        .collect();
+    let mut by_window: HashMap<String, Vec<TextElement>> = HashMap::new();
+    let mut unattributed = Vec::new();
+    for e in elements {
+        let cx = i64::from(e.x) + i64::from(e.w / 2);
+        let cy = i64::from(e.y) + i64::from(e.h / 2);
+        let owner = top_down.iter().find(|w| {
+            let g = w.geometry;
+            (i64::from(g.x)..i64::from(g.x) + i64::from(g.w)).contains(&cx)
+                && (i64::from(g.y)..i64::from(g.y) + i64::from(g.h)).contains(&cy)
+        });
+        match owner {
+            Some(w) => by_window.entry(w.id.clone()).or_default().push(e.clone()),
+            None => unattributed.push(e.clone()),
+        }
+    }
+    (by_window, unattributed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::occlusion::Visibility;
+    use crate::WindowMeta;
+
+    fn window(id: &str, x: i32, y: i32, w: u32, h: u32) -> ListedWindow {
+        ListedWindow {
+            id: id.to_string(),
+            geometry: Geometry { x, y, w, h },
+            title: String::new(),
+            desktop: 0,
+            meta: WindowMeta::default(),
+        }
+    }
+
+    fn word(text: &str, x: i32, y: i32) -> TextElement {
+        TextElement {
+            text: text.to_string(),
+            x,
+            y,
+            w: 40,
+            h: 14,
+            confidence: 90.0,
+            visibility: Visibility::Visible,
+            id: None,
+        }
+    }
+
+    #[test]
+    fn test_attribute_to_topmost_window() {
+        let back = window("back", 0, 0, 400, 300);
+        let front = window("front", 200, 100, 400, 300);
+        let hidden = window("hidden", 0, 0, 800, 600);
+        let stacking = ["back".to_string(), "front".to_string()];
+        let elements = [
+            word("behind", 10, 10),
+            word("overlap", 250, 150),
+            word("bar", 700, 580),
+        ];
+        let (by_window, unattributed) = attribute(&elements, &stacking, &[&back, &front, &hidden]);
+        assert_eq!(by_window["back"][0].text, "behind");
+        assert_eq!(by_window["front"][0].text, "overlap");
+        // Not stacked, so not on screen, even though it would contain it.
+        assert!(!by_window.contains_key("hidden"));
+        assert_eq!(unattributed[0].text, "bar");
+    }
+}
@@ -0,0 +1,293 @@
+//! Re-OCR only the parts of a changed window that were actually repainted.
+//!
+//! Design decision: a window is cut into full-width horizontal bands of
+//! `TILE_HEIGHT` pixels whose hashes are stored with its OCR result. When the
+//! window changes, only the bands whose hash differs are cropped and sent to
+//! tesseract; elements from clean bands are carried over from the previous
+//! result. Bands span the whole width because text runs horizontally: a
+//! typed character dirties one band instead of a column of square tiles, and
+//! tesseract keeps the left/right context it needs to segment words.
+//!
+//! A dirty span is grown to cover every previous element it touches, so a
+//! word straddling a band edge is re-read whole rather than kept half-stale.
+//! The crop adds a small margin for context, but only elements centred inside
+//! the span are taken from it; elements in the margin keep their cached copy.
+//! When most of the window is dirty, a single full OCR is cheaper and more
+//! accurate than many crops, so the caller falls back to it.
+
+use std::path::Path;
+
+use image::{imageops, RgbaImage};
+use sha2::{Digest, Sha256};
+
+use crate::ocr::{self, Reader};
+use crate::preprocess::Mode;
+use crate::{Geometry, OcrResult, TextElement};
+
+/// Height of one band; roughly two lines of UI text.
+pub const TILE_HEIGHT: u32 = 32;
+
+/// Context added above and below each crop.
+const MARGIN: u32 = 8;
+
+/// Above this fraction of dirty bands, re-OCR the whole window instead.
+const MAX_DIRTY_FRACTION: f64 = 0.5;
+
+/// Hash of every band of `image`, top to bottom.
+pub fn tile_hashes(image: &RgbaImage) -> Vec<String> {
+    let row_bytes = image.width() as usize * 4;
+    image
+        .as_raw()
+        .chunks(row_bytes * TILE_HEIGHT as usize)
+        .map(|band| {
+            let mut hasher = Sha256::new();
+            hasher.update(image.width().to_le_bytes());
+            hasher.update(band);
+            // 64 bits is plenty to tell bands of one window apart and keeps
+            // the state file small.
+            format!("{:x}", hasher.finalize())[..16].to_string()
+        })
+        .collect()
+}
+
+/// OCR the bands of `image` that differ from `previous`, reusing the rest.
+///
+/// `origin` is where `image` sits inside the window; `previous` and the
+/// result hold window-relative elements like every `OcrResult`. Returns
+/// `None` when the previous result has no comparable bands (first OCR,
+/// resized window, older state file), was read with other settings, or too
+/// much changed; the caller then runs a full OCR.
+pub fn ocr_dirty(
+    reader: Reader,
+    image: &RgbaImage,
+    origin: &Geometry,
+    tiles: &[String],
+    previous: &OcrResult,
+    dir: &Path,
+    window_id: &str,
+) -> Option<OcrResult> {
+    if previous.tiles.len() != tiles.len()
+        || tiles.is_empty()
+        || previous.settings != reader.settings.fingerprint()
+        // Bands would be read without the retry that produced it.
+        || (previous.preprocessed && reader.settings.preprocess != Mode::Always)
+    {
+        return None;
+    }
+    let dirty: Vec<usize> = (0..tiles.len())
+        .filter(|&i| tiles[i] != previous.tiles[i])
+        .collect();
+    if dirty.len() as f64 > tiles.len() as f64 * MAX_DIRTY_FRACTION {
+        return None;
+    }
+
+    // Work in image coordinates, where the bands are.
+    let shift = |e: &TextElement, sign: i32| TextElement {
+        x: e.x + sign * origin.x,
+        y: e.y + sign * origin.y,
+        ..e.clone()
+    };
+    let before: Vec<TextElement> = previous.elements.iter().map(|e| shift(e, -1)).collect();
+    let spans = dirty_spans(&dirty, image.height(), &before);
+    let mut elements: Vec<TextElement> = before
+        .into_iter()
+        .filter(|e| !spans.iter().any(|&span| overlaps(e, span)))
+        .collect();
+
+    for (n, &(top, bottom)) in spans.iter().enumerate() {
+        let crop_top = top.saturating_sub(MARGIN);
+        let crop_bottom = (bottom + MARGIN).min(image.height());
+        let crop = imageops::crop_imm(image, 0, crop_top, image.width(), crop_bottom - crop_top)
+            .to_image();
+        let path = dir.join(format!("{window_id}.tile{n}.png"));
+        if let Err(e) = crop.save(&path) {
+            eprintln!(
+                "[lg-inspect] warning: failed to save {}: {e}",
+                path.display()
+            );
+            return None;
+        }
+        let origin = Geometry {
+            x: 0,
+            y: i32::try_from(crop_top).unwrap_or(i32::MAX),
+            w: crop.width(),
+            h: crop.height(),
+        };
+        // A failed crop falls back to a full OCR, which reports the error.
+        // No `auto` retry: a band with little text in it is normal.
+        let found = reader
+            .read_once(&crop, &path.to_string_lossy(), &origin)
+            .ok()?
+            .elements;
+        elements.extend(found.into_iter().filter(|e| {
+            let centre = i64::from(e.y) + i64::from(e.h / 2);
+            (i64::from(top)..i64::from(bottom)).contains(&centre)
+        }));
+    }
+
+    let mut elements: Vec<TextElement> = elements.iter().map(|e| shift(e, 1)).collect();
+    elements.sort_by_key(|e| (e.y, e.x));
+    Some(OcrResult {
+        text: ocr::text_from_elements(&elements),
+        elements,
+        tiles: tiles.to_vec(),
+        // Pieced together from several OCR runs, so no single layout.
+        lines: Vec::new(),
+        settings: previous.settings.clone(),
+        preprocessed: previous.preprocessed,
+    })
+}
+
+/// Pixel row spans `[top, bottom)` covering the dirty bands, merged and grown
+/// until no previous element crosses a span edge.
+fn dirty_spans(dirty: &[usize], height: u32, elements: &[TextElement]) -> Vec<(u32, u32)> {
+    let mut spans: Vec<(u32, u32)> = Vec::new();
+    for &i in dirty {
+        let top = i as u32 * TILE_HEIGHT;
+        let bottom = (top + TILE_HEIGHT).min(height);
+        match spans.last_mut() {
+            Some(last) if last.1 >= top => last.1 = bottom,
+            _ => spans.push((top, bottom)),
+        }
+    }
+
+    loop {
+        let mut grown = false;
+        for span in &mut spans {
+            let covered =
+                elements
+                    .iter()
+                    .filter(|e| overlaps(e, *span))
+                    .fold(*span, |(top, bottom), e| {
+                        let e_top = u32::try_from(e.y).unwrap_or(0);
+                        (top.min(e_top), bottom.max((e_top + e.h).min(height)))
+                    });
+            if covered != *span {
+                *span = covered;
+                grown = true;
+            }
+        }
+        spans.sort_unstable();
+        spans.dedup_by(|next, prev| {
+            if next.0 <= prev.1 {
+                prev.1 = prev.1.max(next.1);
+                true
+            } else {
+                false
+            }
+        });
+        if !grown {
+            return spans;
+        }
+    }
+}
+
+fn overlaps(element: &TextElement, (top, bottom): (u32, u32)) -> bool {
+    let y = i64::from(element.y);
+    y < i64::from(bottom) && y + i64::from(element.h) > i64::from(top)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{scratch_dir, solid, FakeOcr};
+    use crate::occlusion::Visibility;
+    use crate::ocr::{OcrSettings, TSV_HEADER};
+    use image::Rgba;
+
+    fn word(text: &str, x: i32, y: i32) -> TextElement {
+        TextElement {
+            text: text.to_string(),
+            x,
+            y,
+            w: 60,
+            h: 14,
+            confidence: 90.0,
+            visibility: Visibility::Visible,
+            id: None,
+        }
+    }
+
+    /// `ocr_dirty` for a fully visible window with id `0x00000001`.
+    fn reocr(
+        engine: &FakeOcr,
+        image: &RgbaImage,
+        previous: &OcrResult,
+        dir: &Path,
+    ) -> Option<OcrResult> {
+        let origin = Geometry {
+            x: 0,
+            y: 0,
+            w: image.width(),
+            h: image.height(),
+        };
+        let tiles = tile_hashes(image);
+        let reader = Reader {
+            engine,
+            settings: &OcrSettings::default(),
+        };
+        ocr_dirty(reader, image, &origin, &tiles, previous, dir, "0x00000001")
+    }
+
+    fn paint_band(image: &mut RgbaImage, top: u32, bottom: u32) {
+        for y in top..bottom {
+            for x in 0..image.width() {
+                image.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_tile_hashes_cover_partial_last_band() {
+        let image = solid(100, TILE_HEIGHT * 3 + 5, [0, 0, 0]);
+        assert_eq!(tile_hashes(&image).len(), 4);
+    }
+
+    #[test]
+    fn test_ocr_dirty_rereads_only_repainted_line() {
+        let dir = scratch_dir("tiles");
+        let before = solid(640, 480, [0, 0, 0]);
+        let previous = OcrResult {
+            elements: vec![word("$", 4, 10), word("old", 4, 200)],
+            tiles: tile_hashes(&before),
+            ..OcrResult::default()
+        };
+
+        // The line at y=200 was repainted (band 6, rows 192..224).
+        let mut after = before.clone();
+        paint_band(&mut after, 198, 214);
+        // Crop starts at 192 - MARGIN = 184, so y=200 is 16 inside it.
+        let tsv = format!("{TSV_HEADER}5\t1\t1\t1\t1\t1\t4\t16\t60\t14\t95\tnew\n");
+        let engine = FakeOcr::default().with("0x00000001.tile0", &tsv);
+
+        let result = reocr(&engine, &after, &previous, &dir).unwrap();
+        assert_eq!(engine.calls(), ["0x00000001.tile0"]);
+        assert_eq!(result.text, "$\nnew");
+        let new = &result.elements[1];
+        assert_eq!((new.x, new.y), (4, 200));
+    }
+
+    #[test]
+    fn test_ocr_dirty_grows_span_over_straddling_word() {
+        let elements = [word("edge", 0, 60)];
+        // Band 1 is rows 32..64; the word runs 60..74 into band 2.
+        assert_eq!(dirty_spans(&[1], 480, &elements), [(32, 74)]);
+    }
+
+    #[test]
+    fn test_ocr_dirty_falls_back_when_mostly_dirty() {
+        let dir = scratch_dir("tiles-fallback");
+        let before = solid(200, 128, [0, 0, 0]);
+        let previous = OcrResult {
+            tiles: tile_hashes(&before),
+            ..OcrResult::default()
+        };
+        let after = solid(200, 128, [255, 255, 255]);
+        let engine = FakeOcr::default();
+        assert!(reocr(&engine, &after, &previous, &dir).is_none());
+        // A resized window has no comparable bands either.
+        let taller = solid(200, 256, [0, 0, 0]);
+        assert!(reocr(&engine, &taller, &previous, &dir).is_none());
+        assert!(engine.calls().is_empty());
+    }
+}